@@ -0,0 +1,170 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+use tokio::{
+    io,
+    net::{lookup_host, TcpStream},
+    time::timeout,
+};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 8000;
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const URL_SCHEME: &str = "minisql://";
+
+/// Host and port of a server, host may be a DNS name, an IPv4 or an IPv6 address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerAddress {
+    fn default() -> Self {
+        ServerAddress { host: DEFAULT_HOST.to_string(), port: DEFAULT_PORT }
+    }
+}
+
+impl Display for ServerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Parses `host`, `host:port`, `[ipv6]:port`, bare `ipv6` and all of them prefixed
+/// with `minisql://`, missing port is replaced by `DEFAULT_PORT`
+impl FromStr for ServerAddress {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let authority = s.strip_prefix(URL_SCHEME).unwrap_or(s).trim_end_matches('/');
+        if authority.is_empty() {
+            return Err(invalid_input(format!("'{}' does not contain a host", s)));
+        }
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| invalid_input(format!("Unclosed '[' in '{}'", s)))?;
+            match rest {
+                "" => (host, None),
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(invalid_input(format!("Unexpected '{}' in '{}'", rest, s))),
+                },
+            }
+        } else if authority.matches(':').count() > 1 {
+            // Unbracketed IPv6 address, there is no way to tell a port apart
+            (authority, None)
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid_input(format!("'{}' does not contain a host", s)));
+        }
+        let port = match port {
+            Some(port) => parse_port(port)?,
+            None => DEFAULT_PORT,
+        };
+        Ok(ServerAddress { host: host.to_string(), port })
+    }
+}
+
+pub fn parse_port(port: &str) -> io::Result<u16> {
+    port.parse().map_err(|_| invalid_input(format!("'{}' is not a valid port", port)))
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Resolves the address and tries every resolved socket address in turn,
+/// each attempt is limited by `connect_timeout`
+pub async fn connect(address: &ServerAddress, connect_timeout: Duration) -> io::Result<TcpStream> {
+    let resolved = timeout(connect_timeout, lookup_host((address.host.as_str(), address.port)))
+        .await
+        .map_err(|_| {
+            io::Error::new(io::ErrorKind::TimedOut, format!("Resolving {} timed out", address))
+        })??;
+
+    let mut last_error = None;
+    for socket_addr in resolved {
+        match timeout(connect_timeout, TcpStream::connect(socket_addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Connecting to {} timed out", socket_addr),
+                ))
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve to any address", address),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(host: &str, port: u16) -> ServerAddress {
+        ServerAddress { host: host.to_string(), port }
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!("localhost".parse::<ServerAddress>().unwrap(), address("localhost", 8000));
+        assert_eq!("10.0.0.1:9000".parse::<ServerAddress>().unwrap(), address("10.0.0.1", 9000));
+        assert_eq!("::1".parse::<ServerAddress>().unwrap(), address("::1", 8000));
+        assert_eq!("[::1]:9000".parse::<ServerAddress>().unwrap(), address("::1", 9000));
+        assert_eq!(
+            "minisql://db.example.com:1234/".parse::<ServerAddress>().unwrap(),
+            address("db.example.com", 1234)
+        );
+        assert_eq!(
+            "minisql://[fe80::1]".parse::<ServerAddress>().unwrap(),
+            address("fe80::1", 8000)
+        );
+    }
+
+    #[test]
+    fn parse_invalid_addresses() {
+        assert!("".parse::<ServerAddress>().is_err());
+        assert!("minisql://".parse::<ServerAddress>().is_err());
+        assert!("host:port".parse::<ServerAddress>().is_err());
+        assert!("host:70000".parse::<ServerAddress>().is_err());
+        assert!("[::1".parse::<ServerAddress>().is_err());
+        assert!(":8000".parse::<ServerAddress>().is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for addr in [address("localhost", 1), address("::1", 8000)] {
+            assert_eq!(addr.to_string().parse::<ServerAddress>().unwrap(), addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_to_listener() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(connect(&address("localhost", port), DEFAULT_CONNECT_TIMEOUT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn connect_refused() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        assert!(connect(&address("127.0.0.1", port), DEFAULT_CONNECT_TIMEOUT).await.is_err());
+    }
+}
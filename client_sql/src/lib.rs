@@ -1,14 +1,20 @@
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{self, AsyncWriteExt, Interest},
-    net::TcpStream,
-};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub mod connection;
+pub mod tls;
+
+/// Plain TCP or TLS connection to the server
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ClientStream for T {}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum CommandType {
     Query,
     Tables,
     Columns,
+    Login { user: String, password: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,37 +29,108 @@ impl Command {
     }
 }
 
-pub async fn write_command_to_stream(stream: &mut TcpStream, command: Command) -> io::Result<()> {
+// Longer messages are refused, a corrupted length would make the reader allocate it
+pub const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Writes a message framed by its length in bytes, a big endian `u32`. Both the client
+/// and the server frame every message this way
+pub async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, message: &str) -> io::Result<()> {
+    if message.len() > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Message is too long"));
+    }
+    stream.write_u32(message.len() as u32).await?;
+    stream.write_all(message.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Reads a message written by `write_message`, None when the connection is closed
+/// before a new message starts
+pub async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<String>> {
+    let length = match stream.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Message is too long"));
+    }
+    let mut message = vec![0; length];
+    stream.read_exact(&mut message).await?;
+    String::from_utf8(message)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Message is not valid UTF-8"))
+}
+
+pub async fn write_command_to_stream<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    command: Command,
+) -> io::Result<()> {
     let serialized = serde_json::to_string(&command).unwrap();
-    stream.write(serialized.as_bytes()).await?;
+    write_message(stream, &serialized).await
+}
 
-    Ok(())
+// Response the server sends after a successful login
+#[derive(Deserialize)]
+enum LoginResponse {
+    Nothing,
 }
 
-pub async fn read_from_stream(stream: &mut TcpStream) -> io::Result<()> {
+/// Whether the server accepted the login, any other response is the reason it was refused
+pub fn is_login_ok(response: &str) -> bool {
+    matches!(ron::from_str(response), Ok(LoginResponse::Nothing))
+}
+
+pub async fn login<S: ClientStream>(
+    stream: &mut S,
+    user: String,
+    password: String,
+) -> io::Result<()> {
+    let command =
+        Command::create_command_from(String::new(), CommandType::Login { user, password });
+    write_command_to_stream(stream, command).await?;
+    let response = read_response(stream).await?;
+    if is_login_ok(&response) {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, response))
+    }
+}
+
+pub async fn read_from_stream<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<()> {
     println!("Message from server:");
-    loop {
-        let ready = stream.ready(Interest::READABLE).await?;
-
-        if ready.is_readable() {
-            let mut buffer = [0u8; 1024];
-            match stream.try_read(&mut buffer) {
-                Ok(data_length) => {
-                    let res = String::from_utf8(buffer[0..data_length].to_vec()).unwrap();
-                    print!("{}", res);
-                    if data_length == 1024 {
-                        continue;
-                    }
-                    println!();
-                    return Ok(());
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(err) => {
-                    return Err(err);
-                }
+    println!("{}", read_response(stream).await?);
+    Ok(())
+}
+
+pub async fn read_response<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    read_message(stream).await?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::ConnectionAborted, "Server closed the connection")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn framing() {
+        let (mut client, mut server) = io::duplex(64);
+        let long = "x".repeat(4096);
+        let writer = tokio::spawn(async move {
+            for message in [long.as_str(), "", "ü"] {
+                write_message(&mut client, message).await.unwrap();
             }
-        }
+            // The length arrives in pieces and the message is not UTF-8
+            client.write_all(&[0, 0]).await.unwrap();
+            client.write_all(&[0, 2, 0xff, 0xfe]).await.unwrap();
+            client
+        });
+        assert_eq!(read_message(&mut server).await.unwrap(), Some("x".repeat(4096)));
+        assert_eq!(read_message(&mut server).await.unwrap(), Some(String::new()));
+        assert_eq!(read_message(&mut server).await.unwrap(), Some("ü".to_string()));
+        let invalid = read_message(&mut server).await.unwrap_err();
+        assert_eq!(invalid.kind(), io::ErrorKind::InvalidData);
+        drop(writer.await.unwrap());
+        assert_eq!(read_message(&mut server).await.unwrap(), None);
     }
 }
@@ -1,14 +1,24 @@
+use client_sql::connection::{connect, parse_port, ServerAddress, DEFAULT_CONNECT_TIMEOUT};
+use client_sql::tls::{connect_tls, TlsOptions};
 use client_sql::*;
 use client_sql::{read_from_stream, write_command_to_stream};
 use std::io::{self as other_io, BufRead};
-use tokio::{
-    io,
-    net::{TcpSocket, TcpStream},
-};
+use std::time::Duration;
+use tokio::io;
+
+const USAGE: &str =
+    "Usage: client_sql [URL] [--host HOST] [--port PORT] [--timeout SECONDS] [--user USER]
+                  [--tls] [--ca-cert FILE] [--insecure]
+  URL has the form [minisql://]HOST[:PORT], IPv6 hosts are written as [::1]:8000
+  The password is read from MINISQL_PASSWORD or asked for interactively
+  --ca-cert trusts the certificates in a PEM file, e.g. a self-signed one, and implies --tls
+  --insecure accepts any server certificate, use only in development, implies --tls";
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let mut stream = connect_to_server().await?;
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(exit_with);
+    let mut stream = connect_to_server(&options).await.unwrap_or_else(exit_with);
+    log_in(&mut stream, options.user).await.unwrap_or_else(exit_with);
     println!("Welcome to miniSQL server!");
 
     loop {
@@ -64,12 +74,99 @@ fn read_input() -> Result<String, other_io::Error> {
     Ok(line)
 }
 
-async fn connect_to_server() -> io::Result<TcpStream> {
-    let addr =
-        std::env::var("SERVER_IP").unwrap_or_else(|_| "127.0.0.1".to_string()).parse().unwrap();
+fn exit_with<T>(error: io::Error) -> T {
+    eprintln!("{}", error);
+    std::process::exit(1);
+}
 
-    let socket = TcpSocket::new_v4()?;
-    let stream = socket.connect(addr).await?;
+struct ConnectOptions {
+    address: ServerAddress,
+    timeout: Duration,
+    user: Option<String>,
+    tls: Option<TlsOptions>,
+}
+
+/// Flags override the URL, which overrides the `SERVER_URL` and `SERVER_IP` variables
+fn parse_args(mut args: impl Iterator<Item = String>) -> io::Result<ConnectOptions> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mut address = match std::env::var("SERVER_URL").or_else(|_| std::env::var("SERVER_IP")) {
+        Ok(url) => url.parse()?,
+        Err(_) => ServerAddress::default(),
+    };
+    let mut host = None;
+    let mut port = None;
+    let mut timeout = DEFAULT_CONNECT_TIMEOUT;
+    let mut user = std::env::var("MINISQL_USER").ok();
+    let mut tls: Option<TlsOptions> = None;
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next().ok_or_else(|| invalid(format!("{} requires a value\n{}", flag, USAGE)))
+        };
+        match arg.as_str() {
+            "--host" => host = Some(value("--host")?),
+            "--port" | "-p" => port = Some(parse_port(&value("--port")?)?),
+            "--user" | "-u" => user = Some(value("--user")?),
+            "--tls" => {
+                tls.get_or_insert_with(TlsOptions::default);
+            }
+            "--ca-cert" => {
+                tls.get_or_insert_with(TlsOptions::default).ca_file =
+                    Some(value("--ca-cert")?.into())
+            }
+            "--insecure" => tls.get_or_insert_with(TlsOptions::default).accept_invalid_certs = true,
+            "--timeout" => {
+                let seconds = value("--timeout")?;
+                let seconds = seconds
+                    .parse()
+                    .map_err(|_| invalid(format!("'{}' is not a number of seconds", seconds)))?;
+                timeout = Duration::from_secs(seconds);
+            }
+            "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            flag if flag.starts_with('-') => {
+                return Err(invalid(format!("Unknown option '{}'\n{}", flag, USAGE)))
+            }
+            url => address = url.parse()?,
+        }
+    }
+    if let Some(host) = host {
+        address.host = host;
+    }
+    if let Some(port) = port {
+        address.port = port;
+    }
+    Ok(ConnectOptions { address, timeout, user, tls })
+}
+
+async fn connect_to_server(options: &ConnectOptions) -> io::Result<Box<dyn ClientStream>> {
+    let error = |e: io::Error| {
+        io::Error::new(e.kind(), format!("Could not connect to {}: {}", options.address, e))
+    };
+    let stream = connect(&options.address, options.timeout).await.map_err(error)?;
+    match &options.tls {
+        Some(tls) => {
+            Ok(Box::new(connect_tls(stream, &options.address.host, tls).await.map_err(error)?))
+        }
+        None => Ok(Box::new(stream)),
+    }
+}
 
-    Ok(stream)
+async fn log_in(stream: &mut Box<dyn ClientStream>, user: Option<String>) -> io::Result<()> {
+    let user = match user {
+        Some(user) => user,
+        None => {
+            println!("User:");
+            read_input()?
+        }
+    };
+    let password = match std::env::var("MINISQL_PASSWORD") {
+        Ok(password) => password,
+        // Not echoed to the terminal
+        Err(_) => rpassword::prompt_password("Password: ")?,
+    };
+    login(stream, user, password)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("Login failed: {}", e)))
 }
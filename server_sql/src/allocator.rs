@@ -2,7 +2,7 @@ use cap::Cap;
 use std::alloc;
 
 #[global_allocator]
-pub static ALLOCATOR: Cap<alloc::System> = Cap::new(alloc::System, usize::max_value());
+pub static ALLOCATOR: Cap<alloc::System> = Cap::new(alloc::System, usize::MAX);
 
 // Sets 16 Mb as the maximum available heap memory for the database
 const DEFAULT_MEMORY_LIMIT: usize = 16_000_000;
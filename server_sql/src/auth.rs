@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::error::DatabaseError;
+
+pub const DEFAULT_ADMIN_USER: &str = "admin";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    Create,
+    Drop,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 6] = [
+        Privilege::Select,
+        Privilege::Insert,
+        Privilege::Update,
+        Privilege::Delete,
+        Privilege::Create,
+        Privilege::Drop,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserAccount {
+    // PHC string, contains the algorithm parameters and the salt
+    password_hash: String,
+    pub superuser: bool,
+    // Privileges granted on all tables, including the ones not created yet
+    pub privileges: HashSet<Privilege>,
+}
+
+impl UserAccount {
+    fn new(password: &str, superuser: bool) -> Self {
+        UserAccount {
+            password_hash: hash_password(password),
+            superuser,
+            privileges: HashSet::new(),
+        }
+    }
+
+    /// A stored hash which cannot be read, e.g. from a damaged snapshot, matches no password
+    fn verify(&self, password: &str) -> Result<(), DatabaseError> {
+        let hash = PasswordHash::new(&self.password_hash)
+            .map_err(|_| DatabaseError::InvalidPasswordHash)?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| DatabaseError::AuthenticationFailed)
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Password hashing failed")
+        .to_string()
+}
+
+/// All accounts allowed to connect to the database
+#[derive(Default)]
+pub struct UserCatalog {
+    users: RwLock<HashMap<String, UserAccount>>,
+}
+
+impl UserCatalog {
+    pub async fn create_user(
+        &self,
+        name: &str,
+        password: &str,
+        superuser: bool,
+    ) -> Result<(), DatabaseError> {
+        let mut users = self.users.write().await;
+        if users.contains_key(name) {
+            return Err(DatabaseError::UserExists);
+        }
+        users.insert(name.to_string(), UserAccount::new(password, superuser));
+        Ok(())
+    }
+
+    /// Creates a superuser or makes an existing user one with the given password
+    pub async fn set_superuser(&self, name: &str, password: &str) {
+        let account = UserAccount::new(password, true);
+        let mut users = self.users.write().await;
+        let privileges = users.remove(name).map(|old| old.privileges).unwrap_or_default();
+        users.insert(name.to_string(), UserAccount { privileges, ..account });
+    }
+
+    pub async fn drop_user(&self, name: &str) -> Result<(), DatabaseError> {
+        let mut users = self.users.write().await;
+        match users.get(name) {
+            None => Err(DatabaseError::UserDoesNotExist),
+            // Removing superusers could leave the database without an administrator
+            Some(account) if account.superuser => Err(DatabaseError::PermissionDenied),
+            Some(_) => {
+                users.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn set_password(&self, name: &str, password: &str) -> Result<(), DatabaseError> {
+        let mut users = self.users.write().await;
+        let account = users.get_mut(name).ok_or(DatabaseError::UserDoesNotExist)?;
+        account.password_hash = hash_password(password);
+        Ok(())
+    }
+
+    pub async fn authenticate(&self, name: &str, password: &str) -> Result<(), DatabaseError> {
+        let Some(account) = self.users.read().await.get(name).cloned() else {
+            return Err(DatabaseError::AuthenticationFailed);
+        };
+        match account.verify(password) {
+            Err(DatabaseError::InvalidPasswordHash) => {
+                // Clients are not told why, the server log is
+                println!("Stored password hash of {} is invalid", name);
+                Err(DatabaseError::AuthenticationFailed)
+            }
+            verified => verified,
+        }
+    }
+
+    pub async fn exists(&self, name: &str) -> bool {
+        self.users.read().await.contains_key(name)
+    }
+
+    pub async fn is_superuser(&self, name: &str) -> bool {
+        self.users.read().await.get(name).is_some_and(|account| account.superuser)
+    }
+
+    /// Superusers and users granted the privilege on all tables
+    pub async fn has_global_privilege(&self, name: &str, privilege: Privilege) -> bool {
+        self.users
+            .read()
+            .await
+            .get(name)
+            .is_some_and(|account| account.superuser || account.privileges.contains(&privilege))
+    }
+
+    pub async fn grant(&self, name: &str, privileges: &[Privilege]) -> Result<(), DatabaseError> {
+        let mut users = self.users.write().await;
+        let account = users.get_mut(name).ok_or(DatabaseError::UserDoesNotExist)?;
+        account.privileges.extend(privileges);
+        Ok(())
+    }
+
+    pub async fn revoke(&self, name: &str, privileges: &[Privilege]) -> Result<(), DatabaseError> {
+        let mut users = self.users.write().await;
+        let account = users.get_mut(name).ok_or(DatabaseError::UserDoesNotExist)?;
+        account.privileges.retain(|privilege| !privileges.contains(privilege));
+        Ok(())
+    }
+
+    /// Copy of all accounts to be written to disk with the tables
+    pub async fn snapshot(&self) -> HashMap<String, UserAccount> {
+        self.users.read().await.clone()
+    }
+
+    /// Replaces all accounts by the ones of a snapshot
+    pub async fn restore(&self, accounts: HashMap<String, UserAccount>) {
+        *self.users.write().await = accounts;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn authenticate() {
+        let catalog = UserCatalog::default();
+        catalog.create_user("alice", "secret", false).await.unwrap();
+        assert!(catalog.authenticate("alice", "secret").await.is_ok());
+        assert!(catalog.authenticate("alice", "Secret").await.is_err());
+        assert!(catalog.authenticate("bob", "secret").await.is_err());
+        assert!(catalog.create_user("alice", "other", false).await.is_err());
+
+        let mut accounts = catalog.snapshot().await;
+        accounts.get_mut("alice").unwrap().password_hash = "damaged".to_string();
+        catalog.restore(accounts).await;
+        let damaged = catalog.authenticate("alice", "secret").await;
+        assert!(matches!(damaged, Err(DatabaseError::AuthenticationFailed)));
+    }
+
+    #[tokio::test]
+    async fn hashes_are_salted() {
+        let catalog = UserCatalog::default();
+        catalog.create_user("alice", "secret", false).await.unwrap();
+        catalog.create_user("bob", "secret", false).await.unwrap();
+        let users = catalog.users.read().await;
+        assert_ne!(users["alice"].password_hash, users["bob"].password_hash);
+        assert!(!users["alice"].password_hash.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn alter_and_drop() {
+        let catalog = UserCatalog::default();
+        catalog.create_user("admin", "admin", true).await.unwrap();
+        catalog.create_user("alice", "secret", false).await.unwrap();
+        catalog.set_password("alice", "new").await.unwrap();
+        assert!(catalog.authenticate("alice", "secret").await.is_err());
+        assert!(catalog.authenticate("alice", "new").await.is_ok());
+        assert!(catalog.drop_user("admin").await.is_err());
+        catalog.drop_user("alice").await.unwrap();
+        assert!(!catalog.exists("alice").await);
+        assert!(catalog.drop_user("alice").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn global_privileges() {
+        let catalog = UserCatalog::default();
+        catalog.create_user("admin", "admin", true).await.unwrap();
+        catalog.create_user("alice", "secret", false).await.unwrap();
+        assert!(catalog.has_global_privilege("admin", Privilege::Drop).await);
+        assert!(!catalog.has_global_privilege("alice", Privilege::Create).await);
+        catalog.grant("alice", &[Privilege::Create, Privilege::Select]).await.unwrap();
+        assert!(catalog.has_global_privilege("alice", Privilege::Create).await);
+        catalog.revoke("alice", &[Privilege::Create]).await.unwrap();
+        assert!(!catalog.has_global_privilege("alice", Privilege::Create).await);
+        assert!(catalog.has_global_privilege("alice", Privilege::Select).await);
+        assert!(catalog.grant("bob", &Privilege::ALL).await.is_err());
+    }
+}
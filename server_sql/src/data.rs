@@ -1,4 +1,4 @@
-use crate::database::DataAttributes;
+use crate::transaction::StoredRow;
 
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use lazy_static::lazy_static;
@@ -6,10 +6,10 @@ use lazy_static::lazy_static;
 use std::collections::BTreeMap;
 use std::fs::{create_dir_all, remove_file, File};
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::sync::MutexGuard;
 
-pub type RecordsData = BTreeMap<i64, DataAttributes>;
+pub type RecordsData = BTreeMap<i64, StoredRow>;
 
 #[derive(Default, Debug)]
 pub struct DataAbstraction {
@@ -117,3 +117,8 @@ fn get_unique_filename() -> String {
     }
     format!("{}/tabledata_{}", *DATA_PATH, FILE_COUNTER.inc())
 }
+
+/// Directory holding the tables written on shutdown
+pub fn snapshot_path() -> PathBuf {
+    Path::new(&*DATA_PATH).join("snapshot")
+}
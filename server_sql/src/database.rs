@@ -1,17 +1,30 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs::{create_dir_all, remove_dir_all, rename, File},
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter},
+    mem::discriminant,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
 
 use atomic_counter::{AtomicCounter, RelaxedCounter};
-use tokio::{
-    join,
-    sync::{Mutex, RwLock},
-};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::data::{DataAbstraction, DataAbstractionLock};
+use crate::auth::{Privilege, UserCatalog};
+use crate::data::{DataAbstraction, DataAbstractionLock, RecordsData};
 use crate::error::DatabaseError;
+use crate::transaction::{
+    RowVersion, StoredRow, Transaction, TransactionManager, TxId, TxView, Write, FROZEN_TX,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Comparison {
     All,
     Higher(DataAttribute),
@@ -20,6 +33,88 @@ pub enum Comparison {
     LowerOrEqual(DataAttribute),
     Equal(DataAttribute),
     NotEqual(DataAttribute),
+    IsNull,
+    IsNotNull,
+    // Compares what the path finds in a JSON column
+    Path(JsonPath, Box<Comparison>),
+    // Compares strings folded by the collation with an already folded literal
+    Collated(Collation, Box<Comparison>),
+}
+
+impl Comparison {
+    /// Whether a value of the compared column satisfies the comparison. Comparing with NULL
+    /// is unknown, so no value matches it unless tested with `IS NULL`
+    pub fn matches(&self, value: &DataAttribute) -> bool {
+        let compared = |attr| value.compare(attr);
+        match self {
+            Comparison::All => true,
+            Comparison::Higher(attr) => compared(attr).is_some_and(Ordering::is_gt),
+            Comparison::HigherOrEqual(attr) => compared(attr).is_some_and(Ordering::is_ge),
+            Comparison::Lower(attr) => compared(attr).is_some_and(Ordering::is_lt),
+            Comparison::LowerOrEqual(attr) => compared(attr).is_some_and(Ordering::is_le),
+            Comparison::Equal(attr) => compared(attr).is_some_and(Ordering::is_eq),
+            Comparison::NotEqual(attr) => compared(attr).is_some_and(Ordering::is_ne),
+            Comparison::IsNull => *value == DataAttribute::None,
+            Comparison::IsNotNull => *value != DataAttribute::None,
+            Comparison::Path(path, comparison) => comparison.matches(&path.find(value)),
+            Comparison::Collated(collation, comparison) => match value {
+                DataAttribute::String(text) => {
+                    comparison.matches(&DataAttribute::String(collation.fold(text)))
+                }
+                _ => comparison.matches(value),
+            },
+        }
+    }
+}
+
+/// Path into a JSON value like `->'user'->>'id'`, the last step may take the found
+/// value out of JSON
+#[derive(PartialEq, Debug, Clone)]
+pub struct JsonPath {
+    pub steps: Vec<JsonStep>,
+    pub unwrap: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    /// Value at the path, unwrapped scalars get the matching type while unwrapped arrays
+    /// and objects become their text. Nothing is found in values which are not JSON
+    pub fn find(&self, value: &DataAttribute) -> DataAttribute {
+        let DataAttribute::Json(text) = value else {
+            return DataAttribute::None;
+        };
+        let Ok(mut found) = serde_json::from_str::<serde_json::Value>(text) else {
+            return DataAttribute::None;
+        };
+        for step in &self.steps {
+            let next = match step {
+                JsonStep::Key(key) => found.get_mut(key),
+                JsonStep::Index(index) => found.get_mut(index),
+            };
+            match next {
+                Some(next) => found = next.take(),
+                None => return DataAttribute::None,
+            }
+        }
+        if !self.unwrap {
+            return DataAttribute::Json(found.to_string());
+        }
+        match found {
+            serde_json::Value::Null => DataAttribute::None,
+            serde_json::Value::Bool(boolean) => DataAttribute::Bool(boolean),
+            serde_json::Value::Number(num) => match num.as_i64() {
+                Some(num) => DataAttribute::Number(num),
+                None => DataAttribute::Real(Float(num.as_f64().unwrap_or(f64::NAN))),
+            },
+            serde_json::Value::String(text) => DataAttribute::String(text),
+            other => DataAttribute::String(other.to_string()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Hash, Eq)]
@@ -30,6 +125,379 @@ pub enum DataAttribute {
     Data(Vec<u8>),
     NoneId,
     None,
+    Real(Float),
+    Bool(bool),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(NaiveDateTime),
+    Decimal(Decimal),
+    // Compact text of a valid JSON value
+    Json(String),
+}
+
+impl DataAttribute {
+    /// Order used by comparisons, numbers of any type are compared by their values. NULL and
+    /// values of unrelated types have no order
+    pub fn compare(&self, other: &DataAttribute) -> Option<Ordering> {
+        Some(match (self, other) {
+            (DataAttribute::None, _) | (_, DataAttribute::None) => return None,
+            (DataAttribute::Number(num), DataAttribute::Real(real)) => Float(*num as f64).cmp(real),
+            (DataAttribute::Real(real), DataAttribute::Number(num)) => {
+                real.cmp(&Float(*num as f64))
+            }
+            (DataAttribute::Decimal(decimal), DataAttribute::Number(num)) => {
+                decimal.cmp(&Decimal::from(*num))
+            }
+            (DataAttribute::Number(num), DataAttribute::Decimal(decimal)) => {
+                Decimal::from(*num).cmp(decimal)
+            }
+            (DataAttribute::Decimal(decimal), DataAttribute::Real(real)) => {
+                Float(decimal.to_f64()).cmp(real)
+            }
+            (DataAttribute::Real(real), DataAttribute::Decimal(decimal)) => {
+                real.cmp(&Float(decimal.to_f64()))
+            }
+            // A date is the midnight starting it
+            (DataAttribute::Date(date), DataAttribute::Timestamp(timestamp)) => {
+                date.and_time(NaiveTime::default()).cmp(timestamp)
+            }
+            (DataAttribute::Timestamp(timestamp), DataAttribute::Date(date)) => {
+                timestamp.cmp(&date.and_time(NaiveTime::default()))
+            }
+            _ if discriminant(self) == discriminant(other) => self.partial_cmp(other)?,
+            _ => return None,
+        })
+    }
+
+    /// What a selected `EXTRACT(..)` or JSON path gives for the value
+    pub fn extract(&self, extraction: &Extraction) -> DataAttribute {
+        match extraction {
+            Extraction::Part(part) => self.date_part(*part),
+            Extraction::Path(path) => path.find(self),
+        }
+    }
+
+    /// Part of a date, time or timestamp as a number, other values have no parts
+    fn date_part(&self, part: DatePart) -> DataAttribute {
+        let (date, time) = match self {
+            DataAttribute::Date(date) => (Some(*date), None),
+            DataAttribute::Time(time) => (None, Some(*time)),
+            DataAttribute::Timestamp(timestamp) => (Some(timestamp.date()), Some(timestamp.time())),
+            _ => return DataAttribute::None,
+        };
+        let value = match part {
+            DatePart::Year => date.map(|date| date.year() as i64),
+            DatePart::Month => date.map(|date| date.month() as i64),
+            DatePart::Day => date.map(|date| date.day() as i64),
+            DatePart::Hour => time.map(|time| time.hour() as i64),
+            DatePart::Minute => time.map(|time| time.minute() as i64),
+            DatePart::Second => time.map(|time| time.second() as i64),
+            // Seconds since the Unix epoch, or since midnight for a time
+            DatePart::Epoch => match (date, time) {
+                (Some(date), time) => {
+                    Some(date.and_time(time.unwrap_or_default()).and_utc().timestamp())
+                }
+                (None, time) => time.map(|time| time.num_seconds_from_midnight() as i64),
+            },
+        };
+        value.map_or(DataAttribute::None, DataAttribute::Number)
+    }
+}
+
+/// What `EXTRACT(part FROM column)` selects
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Epoch,
+}
+
+/// Value selected instead of a column, a part of a date or a value inside JSON
+#[derive(PartialEq, Debug)]
+pub enum Extraction {
+    Part(DatePart),
+    Path(JsonPath),
+}
+
+/// Positions in a selected row with the values to answer instead
+pub type Extracted = Vec<(usize, Extraction)>;
+
+impl DatePart {
+    /// Whether values of the type have the part
+    pub fn applies_to(&self, attribute_type: &AttributeType) -> bool {
+        match self {
+            DatePart::Year | DatePart::Month | DatePart::Day => {
+                matches!(attribute_type, AttributeType::Date | AttributeType::Timestamp)
+            }
+            DatePart::Hour | DatePart::Minute | DatePart::Second => {
+                matches!(attribute_type, AttributeType::Time | AttributeType::Timestamp)
+            }
+            DatePart::Epoch => matches!(
+                attribute_type,
+                AttributeType::Date | AttributeType::Time | AttributeType::Timestamp
+            ),
+        }
+    }
+}
+
+/// Floating point number with a total order, NaN equals NaN and is above every other
+/// number while zero equals negative zero
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.0.partial_cmp(&other.0).unwrap(),
+        }
+    }
+}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal numbers have to hash the same
+        let normalized = match self.0 {
+            real if real.is_nan() => f64::NAN,
+            // Turns negative zero into zero
+            real => real + 0.0,
+        };
+        normalized.to_bits().hash(state);
+    }
+}
+
+// JSON has no NaN or infinities, they are written as strings
+impl Serialize for Float {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f64(self.0)
+        } else {
+            serializer.serialize_str(&self.0.to_string())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Float {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Finite(f64),
+            Other(String),
+        }
+        match Stored::deserialize(deserializer)? {
+            Stored::Finite(real) => Ok(Float(real)),
+            Stored::Other(real) => real.parse().map(Float).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Most digits a decimal may have, the digits have to fit into an `i64`
+pub const MAX_PRECISION: u32 = 18;
+
+/// Exact decimal number, `digits` shifted right by `scale` decimal places. Values
+/// equal regardless of their scale, `1.50` equals `1.5`
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    digits: i64,
+    scale: u32,
+}
+
+impl Decimal {
+    /// The same number with `scale` decimal places, rounded half away from zero,
+    /// None when it does not fit
+    pub fn rescale(&self, scale: u32) -> Option<Decimal> {
+        let digits = if scale >= self.scale {
+            self.digits.checked_mul(10i64.checked_pow(scale - self.scale)?)?
+        } else {
+            divide_rounded(self.digits as i128, 10i128.pow(self.scale - scale)) as i64
+        };
+        Some(Decimal { digits, scale })
+    }
+
+    /// Exact sum, the result has the larger scale of both numbers
+    pub fn checked_add(&self, other: &Decimal) -> Result<Decimal, DatabaseError> {
+        let scale = self.scale.max(other.scale);
+        let digits = self
+            .rescale(scale)
+            .zip(other.rescale(scale))
+            .and_then(|(lhs, rhs)| lhs.digits.checked_add(rhs.digits))
+            .ok_or(DatabaseError::NumericOverflow)?;
+        Decimal { digits, scale }.within_precision()
+    }
+
+    /// Exact difference, the result has the larger scale of both numbers
+    pub fn checked_sub(&self, other: &Decimal) -> Result<Decimal, DatabaseError> {
+        let digits = other.digits.checked_neg().ok_or(DatabaseError::NumericOverflow)?;
+        self.checked_add(&Decimal { digits, scale: other.scale })
+    }
+
+    /// Product with the scales of both numbers added up, places beyond `MAX_PRECISION`
+    /// are rounded half away from zero
+    pub fn checked_mul(&self, other: &Decimal) -> Result<Decimal, DatabaseError> {
+        let product = self.digits as i128 * other.digits as i128;
+        let scale = self.scale + other.scale;
+        let (product, scale) = match scale.checked_sub(MAX_PRECISION) {
+            Some(extra) if extra > 0 => (divide_rounded(product, 10i128.pow(extra)), MAX_PRECISION),
+            _ => (product, scale),
+        };
+        let digits = i64::try_from(product).map_err(|_| DatabaseError::NumericOverflow)?;
+        Decimal { digits, scale }.within_precision()
+    }
+
+    /// Decimals have at most `MAX_PRECISION` digits, all of them may be decimal places
+    fn within_precision(self) -> Result<Decimal, DatabaseError> {
+        if self.scale <= MAX_PRECISION && self.fits(MAX_PRECISION) {
+            Ok(self)
+        } else {
+            Err(DatabaseError::NumericOverflow)
+        }
+    }
+
+    /// Whether the number has at most `precision` digits
+    pub fn fits(&self, precision: u32) -> bool {
+        self.digits.unsigned_abs() < 10u64.pow(precision)
+    }
+
+    /// Nearest real number, both parts are exact as long as `digits` has at most 15 digits
+    pub fn to_f64(&self) -> f64 {
+        self.digits as f64 / 10f64.powi(self.scale as i32)
+    }
+}
+
+/// Quotient rounded half away from zero
+fn divide_rounded(dividend: i128, divisor: i128) -> i128 {
+    let (quotient, remainder) = (dividend / divisor, dividend % divisor);
+    if remainder.unsigned_abs() * 2 >= divisor.unsigned_abs() {
+        quotient + dividend.signum()
+    } else {
+        quotient
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(num: i64) -> Self {
+        Decimal { digits: num, scale: 0 }
+    }
+}
+
+/// Decimal literal with an optional exponent, like `-12.50` or `1.5e3`
+impl FromStr for Decimal {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "Invalid decimal";
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse().map_err(|_| INVALID)?),
+            None => (text, 0i64),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(mantissa) => (true, mantissa),
+            None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let mut digits = integer.bytes().chain(fraction.bytes());
+        if integer.len() + fraction.len() == 0 || !digits.clone().all(|d| d.is_ascii_digit()) {
+            return Err(INVALID);
+        }
+        let digits = digits
+            .try_fold(0i64, |num, d| num.checked_mul(10)?.checked_add((d - b'0') as i64))
+            .ok_or(INVALID)?;
+        let digits = if negative { -digits } else { digits };
+        let scale = (fraction.len() as i64).checked_sub(exponent).ok_or(INVALID)?;
+        let shift = u32::try_from(scale.unsigned_abs()).map_err(|_| INVALID)?;
+        let decimal = if scale >= 0 {
+            Decimal { digits, scale: shift }
+        } else {
+            let digits = 10i64.checked_pow(shift).and_then(|f| digits.checked_mul(f));
+            Decimal { digits: digits.ok_or(INVALID)?, scale: 0 }
+        };
+        decimal.within_precision().map_err(|_| INVALID)
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.digits < 0 { "-" } else { "" };
+        let digits =
+            format!("{:0>width$}", self.digits.unsigned_abs(), width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        match fraction {
+            "" => write!(f, "{}{}", sign, integer),
+            _ => write!(f, "{}{}.{}", sign, integer, fraction),
+        }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        match (self.rescale(scale), other.rescale(scale)) {
+            (Some(lhs), Some(rhs)) => lhs.digits.cmp(&rhs.digits),
+            // Only the larger number in magnitude can overflow
+            (None, _) => self.digits.cmp(&0),
+            (_, None) => 0.cmp(&other.digits),
+        }
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal numbers have to hash the same, trailing zeros are dropped
+        let mut normalized = *self;
+        while normalized.scale > 0 && normalized.digits % 10 == 0 {
+            normalized = Decimal { digits: normalized.digits / 10, scale: normalized.scale - 1 };
+        }
+        (normalized.digits, normalized.scale).hash(state);
+    }
+}
+
+// Written as a string to keep every digit
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
@@ -37,30 +505,179 @@ pub struct DataAttributes {
     pub attributes: Vec<DataAttribute>,
 }
 
-#[derive(PartialEq)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub enum AttributeType {
     Id,
     String,
     Number,
     Data,
+    Real,
+    Bool,
+    Date,
+    Time,
+    Timestamp,
+    Decimal { precision: u32, scale: u32 },
+    Json,
 }
 
-#[derive(PartialEq)]
+/// How strings of a column are compared. `Unicode` only lowercases the text, which is
+/// not full case folding: `ß` still differs from `SS` and a final `ς` from `σ`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Collation {
+    // Byte order of the text
+    #[default]
+    Binary,
+    // ASCII letters compare regardless of case
+    NoCase,
+    // Letters of any script compare regardless of case, as far as lowercasing goes
+    Unicode,
+}
+
+impl Collation {
+    /// Text which compares by bytes the way the original compares by the collation
+    pub fn fold(&self, text: &str) -> String {
+        match self {
+            Collation::Binary => text.to_string(),
+            Collation::NoCase => text.to_ascii_lowercase(),
+            Collation::Unicode => text.to_lowercase(),
+        }
+    }
+}
+
+/// Column the selected rows are sorted by, strings are sorted by its collation
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct OrderBy {
+    pub attr_pos: usize,
+    pub descending: bool,
+}
+
+impl OrderBy {
+    /// NULLs come last in ascending order. Values of unrelated types have no order and
+    /// keep their places
+    fn compare(&self, lhs: Option<&DataAttribute>, rhs: Option<&DataAttribute>) -> Ordering {
+        let ordering = match (lhs, rhs) {
+            (Some(DataAttribute::None), Some(DataAttribute::None)) => Ordering::Equal,
+            (Some(DataAttribute::None), _) => Ordering::Greater,
+            (_, Some(DataAttribute::None)) => Ordering::Less,
+            (Some(lhs), Some(rhs)) => lhs.compare(rhs).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Attribute {
     pub name: String,
     pub attribute_type: AttributeType,
+    #[serde(default)]
+    pub not_null: bool,
+    // Stored in rows inserted without a value for the column
+    #[serde(default)]
+    pub default: Option<DataAttribute>,
+    #[serde(default)]
+    pub collation: Collation,
+}
+
+impl Attribute {
+    pub fn new(name: &str, attribute_type: AttributeType) -> Self {
+        Attribute {
+            name: name.to_string(),
+            attribute_type,
+            not_null: false,
+            default: None,
+            collation: Collation::Binary,
+        }
+    }
+
+    /// Value of the column in rows inserted without one
+    pub fn default_value(&self) -> DataAttribute {
+        self.default.clone().unwrap_or(DataAttribute::None)
+    }
+}
+
+/// What an insert does when the primary key of a row is taken
+#[derive(PartialEq, Default)]
+pub enum OnConflict {
+    #[default]
+    Error,
+    Nothing,
+    Update(Assignments),
+}
+
+#[derive(PartialEq, Clone)]
+pub enum SetValue {
+    Value(DataAttribute),
+    // Column of the row proposed for insertion
+    Excluded(usize),
+}
+
+/// Positions of the changed columns with their new values
+pub type Assignments = Vec<(usize, SetValue)>;
+
+/// Change made to the rows matched by a `DELETE` or an `UPDATE`
+#[derive(PartialEq)]
+pub enum Change {
+    Delete,
+    Update(Assignments),
+}
+
+/// Rows changed by a statement, with the returned columns when any were requested
+#[derive(Default)]
+pub struct Changed {
+    pub ids: Vec<i64>,
+    pub rows: Vec<DataAttributes>,
+}
+
+impl Changed {
+    fn append(&mut self, mut other: Changed) {
+        self.ids.append(&mut other.ids);
+        self.rows.append(&mut other.rows);
+    }
+}
+
+pub enum InsertOutcome {
+    Inserted,
+    Updated,
+    Skipped,
+}
+
+/// Change made by `ALTER TABLE`
+#[derive(PartialEq)]
+pub enum AlterTable {
+    AddColumn { attribute: Attribute },
+    DropColumn { name: String },
+    RenameColumn { name: String, new_name: String },
+    Rename { new_name: String },
 }
 
 #[derive(Default)]
 pub struct DatabaseTable {
     pub attributes: Vec<Attribute>,
     pub counter: RelaxedCounter,
+    // The owner implicitly holds every privilege on the table
+    pub owner: String,
+    pub grants: HashMap<String, HashSet<Privilege>>,
+}
+
+impl DatabaseTable {
+    fn has_privilege(&self, user: &str, privilege: Privilege) -> bool {
+        self.owner == user
+            || self.grants.get(user).is_some_and(|grants| grants.contains(&privilege))
+    }
 }
 
-#[derive(Serialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum DatabaseResponse {
     Nothing,
     Id(i64),
+    Ids(Vec<i64>),
+    // Rows deleted or updated by a statement
+    Count(usize),
     Data(Vec<DataAttributes>),
     Names(Vec<String>),
 }
@@ -76,16 +693,20 @@ impl TableDataChunk {
         DataAbstractionLock::new(self.data.lock().await)
     }
 
-    pub async fn select_by_closure(
+    pub async fn select_matching(
         &self,
         attr_pos: usize,
-        att_clone: &DataAttribute,
+        comparison: &Comparison,
         selected: Arc<Vec<usize>>,
-        comparison: fn(&DataAttribute, &DataAttribute) -> bool,
+        view: &TxView,
     ) -> Vec<DataAttributes> {
         let mut ret = vec![];
         let lock = self.lock_data().await;
-        for item in lock.values().filter(|elem| comparison(&elem.attributes[attr_pos], att_clone)) {
+        for item in lock
+            .values()
+            .filter_map(|row| view.visible_data(row))
+            .filter(|elem| comparison.matches(&elem.attributes[attr_pos]))
+        {
             let mut data_attr = DataAttributes::default();
             for i in selected.iter() {
                 data_attr.attributes.push(item.attributes[*i].clone());
@@ -95,23 +716,95 @@ impl TableDataChunk {
         ret
     }
 
-    pub async fn delete_by_closure(
+    /// Marks the visible versions of the rows as deleted by the transaction, an update adds
+    /// the new versions on top of them. Returned columns are taken from the deleted or the
+    /// new versions
+    fn mark_changed(
+        records: &mut RecordsData,
+        ids: Vec<i64>,
+        change: &Change,
+        returned: &[usize],
+        view: &TxView,
+    ) -> Result<Changed, DatabaseError> {
+        let conflict = |row: &RowVersion| match change {
+            Change::Delete => !view.can_delete(view.visible(row).unwrap()),
+            Change::Update(_) => !view.can_replace(row),
+        };
+        let taken = ids
+            .iter()
+            .map(|id| (*id, Self::take_row(records, *id, view).unwrap()))
+            .collect::<Vec<_>>();
+        // Either every matching row of the chunk is changed or none of them
+        if taken.iter().any(|(_, row)| conflict(row)) {
+            for (id, row) in taken {
+                records.insert(id, row.into());
+            }
+            return Err(DatabaseError::DataConflict);
+        }
+        let mut rows = vec![];
+        for (id, mut row) in taken {
+            let row = match change {
+                Change::Delete => {
+                    let version = row.find_mut(|version| view.sees(version)).unwrap();
+                    version.xmax = Some(view.id);
+                    if !returned.is_empty() {
+                        rows.push(project(&version.data, returned));
+                    }
+                    row
+                }
+                Change::Update(set) => {
+                    let data = update_row(set, &row.data, &row.data);
+                    if !returned.is_empty() {
+                        rows.push(project(&data, returned));
+                    }
+                    let row = RowVersion { xmax: Some(view.id), ..row };
+                    RowVersion::new(view.id, data).replacing(row)
+                }
+            };
+            records.insert(id, row.into());
+        }
+        Ok(Changed { ids, rows })
+    }
+
+    pub async fn change_matching(
         &self,
         attr_pos: usize,
-        att_clone: &DataAttribute,
-        comparison: fn(&DataAttribute, &DataAttribute) -> bool,
-    ) {
-        self.lock_data().await.retain(|_, item| !comparison(&item.attributes[attr_pos], att_clone));
+        comparison: &Comparison,
+        change: &Change,
+        returned: &[usize],
+        view: &TxView,
+    ) -> Result<Changed, DatabaseError> {
+        let mut lock = self.lock_data().await;
+        let ids = lock
+            .iter()
+            .filter_map(|(id, row)| Some((id, view.visible_data(row)?)))
+            .filter(|(_, data)| comparison.matches(&data.attributes[attr_pos]))
+            .map(|(id, _)| *id)
+            .collect();
+        Self::mark_changed(&mut lock, ids, change, returned, view)
     }
 
-    pub async fn delete_by_id(&self, id: i64) {
+    pub async fn change_by_id(
+        &self,
+        id: i64,
+        change: &Change,
+        returned: &[usize],
+        view: &TxView,
+    ) -> Result<Changed, DatabaseError> {
         let mut lock = self.lock_data().await;
-        lock.remove(&id);
+        let visible = lock.get(&id).and_then(|row| view.visible_data(row));
+        let ids = visible.map(|_| vec![id]).unwrap_or_default();
+        Self::mark_changed(&mut lock, ids, change, returned, view)
     }
 
-    pub async fn get_by_id(&self, id: i64, selected: Vec<usize>) -> Vec<DataAttributes> {
+    pub async fn get_by_id(
+        &self,
+        id: i64,
+        selected: Vec<usize>,
+        view: &TxView,
+    ) -> Vec<DataAttributes> {
         let lock = self.lock_data().await;
-        let item = lock.get(&id);
+        let item = lock.get(&id).and_then(|row| view.visible_data(row));
         if item.is_none() {
             return vec![];
         }
@@ -123,10 +816,174 @@ impl TableDataChunk {
         vec![attributes]
     }
 
-    pub async fn add(&self, id: i64, data: DataAttributes) {
+    pub async fn add(&self, id: i64, row: RowVersion) {
+        let mut lock = self.lock_data().await;
+        lock.insert(id, row.into());
+    }
+
+    /// Takes the versions of a row out of the chunk, dropping the ones of aborted
+    /// transactions since nobody sees them
+    fn take_row(records: &mut RecordsData, id: i64, view: &TxView) -> Option<RowVersion> {
+        records.remove(&id).and_then(|row| RowVersion::from(row).without(&|v| view.is_dead(v)))
+    }
+
+    /// Adds a new row unless the id is taken, the row is given back then. The id of a row
+    /// deleted by the transaction or before its snapshot is free again
+    pub async fn insert(&self, id: i64, row: RowVersion, view: &TxView) -> Result<(), RowVersion> {
+        let mut lock = self.lock_data().await;
+        match Self::take_row(&mut lock, id, view) {
+            None => lock.insert(id, row.into()),
+            Some(existing) if view.is_deleted(&existing) => {
+                lock.insert(id, row.replacing(existing).into())
+            }
+            Some(existing) => {
+                lock.insert(id, existing.into());
+                return Err(row);
+            }
+        };
+        Ok(())
+    }
+
+    /// Like `insert`, a taken id is handled as requested. The check and the write happen
+    /// under the chunk lock, so concurrent upserts of an id never add it twice. The columns
+    /// are needed to check the updated row
+    pub async fn upsert(
+        &self,
+        id: i64,
+        row: RowVersion,
+        on_conflict: &OnConflict,
+        attributes: &[Attribute],
+        view: &TxView,
+    ) -> Result<InsertOutcome, DatabaseError> {
+        let mut lock = self.lock_data().await;
+        let existing = match Self::take_row(&mut lock, id, view) {
+            None => {
+                lock.insert(id, row.into());
+                return Ok(InsertOutcome::Inserted);
+            }
+            Some(existing) if view.is_deleted(&existing) => {
+                lock.insert(id, row.replacing(existing).into());
+                return Ok(InsertOutcome::Inserted);
+            }
+            Some(existing) => existing,
+        };
+        let (kept, outcome) = match on_conflict {
+            OnConflict::Error => (existing, Err(DatabaseError::DataConflict)),
+            OnConflict::Nothing => (existing, Ok(InsertOutcome::Skipped)),
+            OnConflict::Update(set) if view.can_replace(&existing) => {
+                let data = update_row(set, &existing.data, &row.data);
+                // Values taken from the proposed row may be NULL
+                match check_not_null(attributes, &data) {
+                    Ok(()) => {
+                        let existing = RowVersion { xmax: Some(view.id), ..existing };
+                        let updated = RowVersion::new(view.id, data).replacing(existing);
+                        (updated, Ok(InsertOutcome::Updated))
+                    }
+                    Err(err) => (existing, Err(err)),
+                }
+            }
+            OnConflict::Update(_) => (existing, Err(DatabaseError::DataConflict)),
+        };
+        lock.insert(id, kept.into());
+        outcome
+    }
+
+    /// A committed insert is made visible to everyone, a rolled back one is removed
+    pub async fn finish_insert(&self, id: i64, tx: TxId, committed: bool) {
+        let mut lock = self.lock_data().await;
+        let Some(row) = lock.remove(&id) else {
+            return;
+        };
+        let mut row = RowVersion::from(row);
+        let row = if committed {
+            if let Some(version) = row.find_mut(|v| v.xmin == tx) {
+                version.xmin = FROZEN_TX;
+            }
+            Some(row)
+        } else {
+            row.without_newest(&|version| version.xmin == tx)
+        };
+        if let Some(row) = row {
+            lock.insert(id, row.into());
+        }
+    }
+
+    /// A committed delete removes the version, a rolled back one restores it
+    pub async fn finish_delete(&self, id: i64, tx: TxId, committed: bool) {
+        let mut lock = self.lock_data().await;
+        let Some(row) = lock.remove(&id) else {
+            return;
+        };
+        let mut row = RowVersion::from(row);
+        let row = if committed {
+            row.without_newest(&|version| version.xmax == Some(tx))
+        } else {
+            if let Some(version) = row.find_mut(|v| v.xmax == Some(tx)) {
+                version.xmax = None;
+            }
+            Some(row)
+        };
+        if let Some(row) = row {
+            lock.insert(id, row.into());
+        }
+    }
+
+    /// Changes the columns of every row version, including the ones spilled to disk
+    pub async fn rewrite(&self, rewrite: &(dyn Fn(&mut Vec<DataAttribute>) + Sync)) {
         let mut lock = self.lock_data().await;
-        lock.insert(id, data);
+        for row in lock.values_mut() {
+            let mut version = match row {
+                StoredRow::Frozen(data) => {
+                    rewrite(&mut data.attributes);
+                    continue;
+                }
+                StoredRow::Versioned(row) => Some(row.as_mut()),
+            };
+            while let Some(current) = version {
+                rewrite(&mut current.data.attributes);
+                version = current.previous.as_deref_mut();
+            }
+        }
+    }
+
+    /// Rows visible to the transaction
+    pub async fn records(&self, view: &TxView) -> BTreeMap<i64, DataAttributes> {
+        let lock = self.lock_data().await;
+        lock.iter().filter_map(|(id, row)| Some((*id, view.visible_data(row)?.clone()))).collect()
+    }
+}
+
+/// Requested columns of a row
+fn project(data: &DataAttributes, selected: &[usize]) -> DataAttributes {
+    DataAttributes { attributes: selected.iter().map(|i| data.attributes[*i].clone()).collect() }
+}
+
+/// Rows have to hold a value in every NOT NULL column
+fn check_not_null(attributes: &[Attribute], data: &DataAttributes) -> Result<(), DatabaseError> {
+    let null = attributes
+        .iter()
+        .zip(data.attributes.iter())
+        .find(|(attr, value)| attr.not_null && **value == DataAttribute::None);
+    match null {
+        Some((attr, _)) => Err(DatabaseError::NotNullViolation(attr.name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// New values of the columns of an updated row
+fn update_row(
+    set: &[(usize, SetValue)],
+    existing: &DataAttributes,
+    proposed: &DataAttributes,
+) -> DataAttributes {
+    let mut data = existing.clone();
+    for (pos, value) in set {
+        data.attributes[*pos] = match value {
+            SetValue::Value(value) => value.clone(),
+            SetValue::Excluded(excluded) => proposed.attributes[*excluded].clone(),
+        };
     }
+    data
 }
 
 pub struct TableData {
@@ -144,251 +1001,769 @@ fn hash_id(id: i64) -> usize {
     (id as u64 & 0b1111_1111) as usize
 }
 
+// Accounts are written next to the tables, table files all end in `.json`
+const USERS_FILE: &str = "users";
+const TABLE_FILE_PREFIX: &str = "table_";
+
+/// File of a table in a snapshot, quoted names may contain any character so they are written
+/// as hex
+fn table_file(name: &str) -> String {
+    let hex = name.bytes().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("{}{}.json", TABLE_FILE_PREFIX, hex)
+}
+
+/// Name of the table stored in a file of a snapshot
+fn table_name(file: &Path) -> Option<String> {
+    if file.extension()? != "json" {
+        return None;
+    }
+    let hex = file.file_stem()?.to_str()?.strip_prefix(TABLE_FILE_PREFIX)?;
+    let bytes =
+        (0..hex.len()).step_by(2).map(|ix| u8::from_str_radix(hex.get(ix..ix + 2)?, 16).ok());
+    String::from_utf8(bytes.collect::<Option<_>>()?).ok()
+}
+
+/// Table as written to disk on shutdown
+#[derive(Serialize, Deserialize)]
+struct TableSnapshot {
+    attributes: Vec<Attribute>,
+    owner: String,
+    grants: HashMap<String, HashSet<Privilege>>,
+    counter: usize,
+    rows: BTreeMap<i64, DataAttributes>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FlushSummary {
+    pub tables: usize,
+    pub rows: usize,
+}
+
+impl Display for FlushSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tables with {} rows", self.tables, self.rows)
+    }
+}
+
 #[derive(Default)]
 pub struct Database {
     pub tables: Arc<RwLock<HashMap<String, DatabaseTable>>>,
     pub data: Arc<RwLock<HashMap<String, TableData>>>,
+    pub users: UserCatalog,
+    pub transactions: Arc<TransactionManager>,
+}
+
+fn new_tabledata() -> [Arc<TableDataChunk>; 256] {
+    (0..256).map(|_| Arc::<TableDataChunk>::default()).collect::<Vec<_>>().try_into().unwrap()
 }
 
-fn new_tabledata() -> [Arc<TableDataChunk>; 256] {
-    (0..256).map(|_| Arc::<TableDataChunk>::default()).collect::<Vec<_>>().try_into().unwrap()
-}
+impl TableData {
+    async fn add(
+        &self,
+        data: DataAttributes,
+        on_conflict: &OnConflict,
+        attributes: &[Attribute],
+        view: &TxView,
+    ) -> Result<(i64, InsertOutcome), DatabaseError> {
+        let explicit = data.attributes.iter().find_map(|att| match att {
+            DataAttribute::Id(id) => Some(*id),
+            _ => None,
+        });
+        if let Some(id) = explicit {
+            // The counter has to be able to move past the id
+            let next = id.checked_add(1).ok_or(DatabaseError::IdOutOfRange)?;
+            let row = RowVersion::new(view.id, data);
+            let outcome =
+                self.chunks[hash_id(id)].upsert(id, row, on_conflict, attributes, view).await?;
+            // Rejected and skipped rows leave the counter alone
+            if matches!(outcome, InsertOutcome::Inserted) {
+                self.advance_counter(next);
+            }
+            return Ok((id, outcome));
+        }
+        let generated = data
+            .attributes
+            .iter()
+            .enumerate()
+            .filter(|(_, att)| **att == DataAttribute::NoneId)
+            .map(|(pos, _)| pos)
+            .collect::<Vec<_>>();
+        let mut row = RowVersion::new(view.id, data);
+        loop {
+            let current_id =
+                i64::try_from(self.counter.inc()).map_err(|_| DatabaseError::IdOutOfRange)?;
+            for pos in generated.iter() {
+                row.data.attributes[*pos] = DataAttribute::Id(current_id);
+            }
+            // Ids taken by explicit inserts are skipped
+            match self.chunks[hash_id(current_id)].insert(current_id, row, view).await {
+                Ok(()) => return Ok((current_id, InsertOutcome::Inserted)),
+                Err(taken) => row = taken,
+            }
+        }
+    }
+
+    /// Generated ids continue after the highest explicit one, `next` is the id following it
+    fn advance_counter(&self, next: i64) {
+        let current = self.counter.get() as i64;
+        if next > current {
+            // Concurrent inserts can only move the counter further
+            self.counter.add((next - current) as usize);
+        }
+    }
+
+    async fn change(
+        &self,
+        attr_pos: usize,
+        comparison: &Comparison,
+        change: Arc<Change>,
+        returned: Arc<Vec<usize>>,
+        view: &TxView,
+        changed: &mut Changed,
+    ) -> Result<(), DatabaseError> {
+        let comparison = Arc::new(comparison.clone());
+        // All chunks are handled asynchronously
+        let mut futures_vec = vec![];
+        for i in 0..256usize {
+            let chunk = self.chunks[i].clone();
+            let comparison = comparison.clone();
+            let change = change.clone();
+            let returned = returned.clone();
+            let view = view.clone();
+            futures_vec.push(tokio::spawn(async move {
+                chunk.change_matching(attr_pos, &comparison, &change, &returned, &view).await
+            }));
+        }
+        // Rows marked in the other chunks are reported even when one of them fails
+        let mut result = Ok(());
+        for handle in futures_vec {
+            match handle.await.unwrap() {
+                Ok(chunk_changed) => changed.append(chunk_changed),
+                Err(e) => result = Err(e),
+            }
+        }
+        result
+    }
+
+    async fn get(
+        &self,
+        attr_pos: usize,
+        comparison: &Comparison,
+        selected: Vec<usize>,
+        view: &TxView,
+    ) -> Vec<DataAttributes> {
+        let comparison = Arc::new(comparison.clone());
+        let selected = Arc::new(selected);
+        let mut futures_vec = vec![];
+        for i in 0..256usize {
+            let comparison = comparison.clone();
+            let selected = selected.clone();
+            let chunk = self.chunks[i].clone();
+            let view = view.clone();
+            let future =
+                async move { chunk.select_matching(attr_pos, &comparison, selected, &view).await };
+            futures_vec.push(tokio::spawn(future));
+        }
+        let mut ret = vec![];
+        for handle in futures_vec.iter_mut() {
+            ret.append(&mut handle.await.unwrap());
+        }
+
+        ret
+    }
+
+    async fn change_id(
+        &self,
+        id: i64,
+        change: &Change,
+        returned: &[usize],
+        view: &TxView,
+        changed: &mut Changed,
+    ) -> Result<(), DatabaseError> {
+        let chunk_id = hash_id(id);
+        let chunk = &self.chunks[chunk_id];
+        changed.append(chunk.change_by_id(id, change, returned, view).await?);
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: i64, selected: Vec<usize>, view: &TxView) -> Vec<DataAttributes> {
+        let chunk_id = hash_id(id);
+        self.chunks[chunk_id].get_by_id(id, selected, view).await
+    }
+
+    async fn is_empty(&self) -> bool {
+        for chunk in self.chunks.iter() {
+            if !chunk.lock_data().await.is_empty() {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn rewrite(&self, rewrite: &(dyn Fn(&mut Vec<DataAttribute>) + Sync)) {
+        for chunk in self.chunks.iter() {
+            chunk.rewrite(rewrite).await;
+        }
+    }
+}
+
+type Schemas = HashMap<String, DatabaseTable>;
+type Tables = HashMap<String, TableData>;
+
+impl Database {
+    /// Locks the schemas and then the data of all tables, the locks are always taken
+    /// in this order and held together, so a statement sees one table definition
+    async fn read_catalog(&self) -> (RwLockReadGuard<'_, Schemas>, RwLockReadGuard<'_, Tables>) {
+        let schemas = self.tables.read().await;
+        (schemas, self.data.read().await)
+    }
+
+    async fn write_catalog(&self) -> (RwLockWriteGuard<'_, Schemas>, RwLockWriteGuard<'_, Tables>) {
+        let schemas = self.tables.write().await;
+        (schemas, self.data.write().await)
+    }
+
+    pub async fn create_table(
+        &self,
+        name: &str,
+        attributes: Vec<Attribute>,
+        owner: &str,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let (mut db_tables, mut db_data) = self.write_catalog().await;
+        if db_tables.contains_key(name) {
+            return Err(DatabaseError::TableExists);
+        }
+        let table = DatabaseTable {
+            attributes,
+            counter: RelaxedCounter::new(0),
+            owner: owner.to_string(),
+            grants: HashMap::new(),
+        };
+        db_tables.insert(name.to_string(), table);
+        db_data.insert(name.to_string(), TableData::default());
+        Ok(DatabaseResponse::Nothing)
+    }
+
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self.transactions.clone())
+    }
+
+    /// Makes the writes visible to transactions started afterwards, the rows
+    /// themselves are finished by `collect_garbage`
+    pub async fn commit(&self, mut tx: Transaction) {
+        let writes = std::mem::take(&mut tx.writes);
+        tx.manager().commit(tx.id(), writes);
+    }
+
+    pub async fn rollback(&self, tx: Transaction) {
+        // Changing the state makes all writes of the transaction invisible at once
+        tx.manager().abort(tx.id());
+        self.finish_writes(tx.id(), &tx.writes, false).await;
+    }
+
+    /// Undoes the writes made after the savepoint, the savepoint itself is kept
+    pub async fn rollback_to_savepoint(
+        &self,
+        tx: &mut Transaction,
+        name: &str,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let undone = tx.rollback_to_savepoint(name)?;
+        self.finish_writes(tx.id(), &undone, false).await;
+        Ok(DatabaseResponse::Nothing)
+    }
+
+    /// Freezes the rows inserted and removes the rows deleted by transactions
+    /// which every snapshot sees as committed, returns the number of such transactions
+    pub async fn collect_garbage(&self) -> usize {
+        let collectable = self.transactions.take_collectable();
+        for (id, writes) in collectable.iter() {
+            self.finish_writes(*id, writes, true).await;
+            self.transactions.forget(*id);
+        }
+        collectable.len()
+    }
+
+    async fn finish_writes(&self, tx: TxId, writes: &[Write], committed: bool) {
+        // Chunks of dropped tables are still finished, nothing reads them anymore. Writes are
+        // undone newest first, as a row may have several versions written by the transaction
+        let mut ordered = writes.iter().collect::<Vec<_>>();
+        if !committed {
+            ordered.reverse();
+        }
+        for write in ordered {
+            match write {
+                Write::Insert { chunk, id } => chunk.finish_insert(*id, tx, committed).await,
+                Write::Delete { chunk, id } => chunk.finish_delete(*id, tx, committed).await,
+            }
+        }
+    }
+
+    pub async fn insert(
+        &self,
+        tx: &mut Transaction,
+        table_name: &str,
+        data: DataAttributes,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        self.insert_rows(tx, table_name, vec![data], &OnConflict::Error, None).await
+    }
+
+    /// Inserts no row when one of them violates a constraint, rows inserted before a taken id
+    /// are undone with the transaction. A single row is answered with its id and several
+    /// rows with the ids of the rows inserted or updated, unless columns are returned
+    pub async fn insert_rows(
+        &self,
+        tx: &mut Transaction,
+        table_name: &str,
+        rows: Vec<DataAttributes>,
+        on_conflict: &OnConflict,
+        returning: Option<Vec<usize>>,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let (schema_lock, read_lock) = self.read_catalog().await;
+        let (Some(table), Some(db_data)) = (schema_lock.get(table_name), read_lock.get(table_name))
+        else {
+            return Err(DatabaseError::TableDoesNotExist);
+        };
+        for data in rows.iter() {
+            check_not_null(&table.attributes, data)?;
+        }
+        if let OnConflict::Update(set) = on_conflict {
+            Self::check_set_not_null(table, set)?;
+        }
+        let mut ids = Vec::with_capacity(rows.len());
+        for data in rows {
+            let (id, outcome) = db_data.add(data, on_conflict, &table.attributes, &tx.view).await?;
+            let chunk = db_data.chunks[hash_id(id)].clone();
+            match outcome {
+                InsertOutcome::Inserted => tx.writes.push(Write::Insert { chunk, id }),
+                InsertOutcome::Updated => {
+                    tx.writes.push(Write::Delete { chunk: chunk.clone(), id });
+                    tx.writes.push(Write::Insert { chunk, id });
+                }
+                InsertOutcome::Skipped => continue,
+            }
+            ids.push(id);
+        }
+
+        if let Some(returned) = returning {
+            // The transaction sees the rows it has just written
+            let mut rows = vec![];
+            for id in ids {
+                rows.append(&mut db_data.get_by_id(id, returned.clone(), &tx.view).await);
+            }
+            return Ok(DatabaseResponse::Data(rows));
+        }
+        match ids.as_slice() {
+            [id] => Ok(DatabaseResponse::Id(*id)),
+            _ => Ok(DatabaseResponse::Ids(ids)),
+        }
+    }
 
-impl TableData {
-    async fn add(&self, mut data: DataAttributes) -> i64 {
-        let current_id = self.counter.inc() as i64;
-        for id in data.attributes.iter_mut().filter(|att| **att == DataAttribute::NoneId) {
-            *id = DataAttribute::Id(current_id);
+    fn check_set_not_null(
+        table: &DatabaseTable,
+        set: &[(usize, SetValue)],
+    ) -> Result<(), DatabaseError> {
+        let null = set.iter().find(|(pos, value)| {
+            table.attributes[*pos].not_null && *value == SetValue::Value(DataAttribute::None)
+        });
+        match null {
+            Some((pos, _)) => {
+                Err(DatabaseError::NotNullViolation(table.attributes[*pos].name.clone()))
+            }
+            None => Ok(()),
         }
-        let chunk_num = hash_id(current_id);
-        self.chunks[chunk_num].add(current_id, data).await;
+    }
 
-        current_id
+    fn is_attr_id(table: &DatabaseTable, attr_pos: usize) -> bool {
+        table.attributes[attr_pos].attribute_type == AttributeType::Id
     }
 
-    async fn delete_closure_comp(
+    pub async fn delete(
         &self,
+        tx: &mut Transaction,
+        table_name: &str,
         attr_pos: usize,
-        att: &DataAttribute,
-        comparison: fn(&DataAttribute, &DataAttribute) -> bool,
-    ) {
-        // All chunks are handled asynchronously
-        let mut futures_vec = vec![];
-        for i in 0..256usize {
-            let chunk = self.chunks[i].clone();
-            let att_clone = (*att).clone();
-            futures_vec.push(tokio::spawn(async move {
-                chunk.delete_by_closure(attr_pos, &att_clone, comparison).await;
-            }));
-        }
-        for handle in futures_vec {
-            handle.await.unwrap();
-        }
+        comparison: &Comparison,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        self.change(tx, table_name, attr_pos, comparison, Change::Delete, None).await
     }
 
-    async fn select_closure_comp(
+    pub async fn update(
         &self,
+        tx: &mut Transaction,
+        table_name: &str,
+        set: Assignments,
         attr_pos: usize,
-        att: &DataAttribute,
-        selected: Vec<usize>,
-        comparison: fn(&DataAttribute, &DataAttribute) -> bool,
-    ) -> Vec<DataAttributes> {
-        let selected = Arc::new(selected);
-        let mut futures_vec = vec![];
-        for i in 0..256usize {
-            let att_clone = (*att).clone();
-            let selected = selected.clone();
-            let chunk = self.chunks[i].clone();
-            let future = async move {
-                chunk.select_by_closure(attr_pos, &att_clone, selected, comparison).await
-            };
-            futures_vec.push(tokio::spawn(future));
-        }
-        let mut ret = vec![];
-        for handle in futures_vec.iter_mut() {
-            ret.append(&mut handle.await.unwrap());
-        }
-
-        ret
+        comparison: &Comparison,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        self.change(tx, table_name, attr_pos, comparison, Change::Update(set), None).await
     }
 
-    async fn delete(&self, attr_pos: usize, comparison: &Comparison) {
-        match comparison {
-            Comparison::All => {
-                self.delete_closure_comp(attr_pos, &DataAttribute::None, |_, _| true)
-            }
-            Comparison::Higher(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num > *att)
-            }
-            Comparison::Lower(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num < *att)
-            }
-            Comparison::Equal(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num == *att)
-            }
-            Comparison::HigherOrEqual(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num >= *att)
-            }
-            Comparison::LowerOrEqual(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num <= *att)
-            }
-            Comparison::NotEqual(attr) => {
-                self.delete_closure_comp(attr_pos, attr, |num, att| *num != *att)
+    /// Deletes or updates the matching rows, answered with the number of changed rows unless
+    /// columns are returned
+    pub async fn change(
+        &self,
+        tx: &mut Transaction,
+        table_name: &str,
+        attr_pos: usize,
+        comparison: &Comparison,
+        change: Change,
+        returning: Option<Vec<usize>>,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let (schema_lock, read_lock) = self.read_catalog().await;
+        let (Some(table), Some(db_data)) = (schema_lock.get(table_name), read_lock.get(table_name))
+        else {
+            return Err(DatabaseError::TableDoesNotExist);
+        };
+        if let Change::Update(set) = &change {
+            Self::check_set_not_null(table, set)?;
+        }
+        let is_id = Self::is_attr_id(table, attr_pos);
+        let change = Arc::new(change);
+        let returned = Arc::new(returning.clone().unwrap_or_default());
+        let mut changed = Changed::default();
+        let result = if let (true, Comparison::Equal(DataAttribute::Id(id))) = (is_id, comparison) {
+            db_data.change_id(*id, &change, &returned, &tx.view, &mut changed).await
+        } else {
+            db_data
+                .change(attr_pos, comparison, change.clone(), returned, &tx.view, &mut changed)
+                .await
+        };
+        // Rows changed by a failed statement are undone with the rest of the writes
+        for id in changed.ids.iter().copied() {
+            let chunk = db_data.chunks[hash_id(id)].clone();
+            tx.writes.push(Write::Delete { chunk: chunk.clone(), id });
+            if let Change::Update(_) = *change {
+                tx.writes.push(Write::Insert { chunk, id });
             }
         }
-        .await;
+        result?;
+        match returning {
+            Some(_) => Ok(DatabaseResponse::Data(changed.rows)),
+            None => Ok(DatabaseResponse::Count(changed.ids.len())),
+        }
     }
 
-    async fn get(
+    pub async fn select(
         &self,
+        tx: &Transaction,
+        table_name: &str,
         attr_pos: usize,
         comparison: &Comparison,
-        selected: Vec<usize>,
-    ) -> Vec<DataAttributes> {
-        match comparison {
-            Comparison::All => {
-                self.select_closure_comp(attr_pos, &DataAttribute::None, selected, |_, _| true)
+        mut selected: Vec<usize>,
+        order_by: Option<OrderBy>,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let (schema_lock, read_lock) = self.read_catalog().await;
+        let (Some(table), Some(db_data)) = (schema_lock.get(table_name), read_lock.get(table_name))
+        else {
+            return Err(DatabaseError::TableDoesNotExist);
+        };
+        // The sorted column is selected last and taken off the rows once they are sorted
+        if let Some(order) = &order_by {
+            selected.push(order.attr_pos);
+        }
+        let is_id = Self::is_attr_id(table, attr_pos);
+        let mut rows = match (is_id, comparison) {
+            (true, Comparison::Equal(DataAttribute::Id(id))) => {
+                db_data.get_by_id(*id, selected, &tx.view).await
             }
-            Comparison::Higher(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num > *att)
+            _ => db_data.get(attr_pos, comparison, selected, &tx.view).await,
+        };
+        if let Some(order) = order_by {
+            let collation = table.attributes[order.attr_pos].collation;
+            for row in rows.iter_mut() {
+                if let Some(DataAttribute::String(text)) = row.attributes.last_mut() {
+                    *text = collation.fold(text);
+                }
             }
-            Comparison::Lower(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num < *att)
+            rows.sort_by(|a, b| order.compare(a.attributes.last(), b.attributes.last()));
+            for row in rows.iter_mut() {
+                row.attributes.pop();
             }
-            Comparison::Equal(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num == *att)
+        }
+        Ok(DatabaseResponse::Data(rows))
+    }
+
+    pub async fn drop_table(&self, table_name: &str) -> Result<DatabaseResponse, DatabaseError> {
+        let (mut db_tables, mut db_data) = self.write_catalog().await;
+        db_tables.remove(table_name);
+        db_data.remove(table_name);
+        Ok(DatabaseResponse::Nothing)
+    }
+
+    /// Rows of the table are rewritten right away, open transactions see the new columns
+    pub async fn alter_table(
+        &self,
+        table_name: &str,
+        alter: AlterTable,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        let (mut db_tables, mut db_data) = self.write_catalog().await;
+        if !db_tables.contains_key(table_name) || !db_data.contains_key(table_name) {
+            return Err(DatabaseError::TableDoesNotExist);
+        }
+        if let AlterTable::Rename { new_name } = &alter {
+            if db_tables.contains_key(new_name) {
+                return Err(DatabaseError::TableExists);
             }
-            Comparison::HigherOrEqual(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num >= *att)
+            let table = db_tables.remove(table_name).unwrap();
+            db_tables.insert(new_name.clone(), table);
+            let table_data = db_data.remove(table_name).unwrap();
+            db_data.insert(new_name.clone(), table_data);
+            return Ok(DatabaseResponse::Nothing);
+        }
+        let table = db_tables.get_mut(table_name).unwrap();
+        let table_data = &db_data[table_name];
+        let position = |name: &str| table.attributes.iter().position(|attr| attr.name == name);
+        match alter {
+            AlterTable::AddColumn { attribute } => {
+                if position(&attribute.name).is_some() {
+                    return Err(DatabaseError::ColumnExists);
+                }
+                // Existing rows get the default
+                let default = attribute.default_value();
+                if attribute.not_null
+                    && default == DataAttribute::None
+                    && !table_data.is_empty().await
+                {
+                    return Err(DatabaseError::NotNullViolation(attribute.name));
+                }
+                table.attributes.push(attribute);
+                table_data.rewrite(&|attributes| attributes.push(default.clone())).await;
             }
-            Comparison::LowerOrEqual(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num <= *att)
+            AlterTable::DropColumn { name } => {
+                let pos = position(&name).ok_or(DatabaseError::ColumnDoesNotExist)?;
+                // Inserted rows get their id in the primary key column
+                if Self::is_attr_id(table, pos) {
+                    return Err(DatabaseError::PrimaryKeyColumn);
+                }
+                table.attributes.remove(pos);
+                table_data
+                    .rewrite(&|attributes| {
+                        attributes.remove(pos);
+                    })
+                    .await;
             }
-            Comparison::NotEqual(attr) => {
-                self.select_closure_comp(attr_pos, attr, selected, |num, att| *num != *att)
+            AlterTable::RenameColumn { name, new_name } => {
+                let pos = position(&name).ok_or(DatabaseError::ColumnDoesNotExist)?;
+                if position(&new_name).is_some() {
+                    return Err(DatabaseError::ColumnExists);
+                }
+                table.attributes[pos].name = new_name;
             }
+            AlterTable::Rename { .. } => unreachable!(),
         }
-        .await
-    }
-
-    async fn delete_id(&self, id: i64) {
-        let chunk_id = hash_id(id);
-        let chunk = &self.chunks[chunk_id];
-        chunk.delete_by_id(id).await;
+        Ok(DatabaseResponse::Nothing)
     }
 
-    async fn get_by_id(&self, id: i64, selected: Vec<usize>) -> Vec<DataAttributes> {
-        let chunk_id = hash_id(id);
-        self.chunks[chunk_id].get_by_id(id, selected).await
+    pub async fn create_user(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<DatabaseResponse, DatabaseError> {
+        self.users.create_user(name, password, false).await?;
+        Ok(DatabaseResponse::Nothing)
     }
-}
 
-impl Database {
-    pub async fn create_table(
+    pub async fn alter_user(
         &self,
         name: &str,
-        attributes: Vec<Attribute>,
+        password: &str,
     ) -> Result<DatabaseResponse, DatabaseError> {
-        let (mut db_tables, mut db_data) = join!(self.tables.write(), self.data.write());
-        if db_tables.contains_key(name) {
-            return Err(DatabaseError::TableExists);
+        self.users.set_password(name, password).await?;
+        Ok(DatabaseResponse::Nothing)
+    }
+
+    pub async fn drop_user(&self, name: &str) -> Result<DatabaseResponse, DatabaseError> {
+        let mut tables = self.tables.write().await;
+        // A user created later under the same name must not inherit the tables or the grants
+        if tables.values().any(|table| table.owner == name) {
+            return Err(DatabaseError::UserOwnsTables);
+        }
+        self.users.drop_user(name).await?;
+        for table in tables.values_mut() {
+            table.grants.remove(name);
         }
-        let table = DatabaseTable { attributes, counter: RelaxedCounter::new(0) };
-        db_tables.insert(name.to_string(), table);
-        db_data.insert(name.to_string(), TableData::default());
         Ok(DatabaseResponse::Nothing)
     }
 
-    pub async fn insert(
+    /// Tables which do not exist can only be accessed with a privilege on all tables
+    pub async fn check_privilege(
         &self,
+        user: &str,
         table_name: &str,
-        data: DataAttributes,
-    ) -> Result<DatabaseResponse, DatabaseError> {
-        let read_lock = self.data.read().await;
-        let db_data = read_lock.get(table_name);
-        if db_data.is_none() {
-            return Err(DatabaseError::TableDoesNotExist);
+        privilege: Privilege,
+    ) -> Result<(), DatabaseError> {
+        if self.users.has_global_privilege(user, privilege).await {
+            return Ok(());
+        }
+        match self.tables.read().await.get(table_name) {
+            Some(table) if table.has_privilege(user, privilege) => Ok(()),
+            _ => Err(DatabaseError::PermissionDenied),
         }
-        let id = db_data.unwrap().add(data).await;
-
-        Ok(DatabaseResponse::Id(id))
     }
 
-    async fn is_attr_id(&self, table_name: &str, attr_pos: usize) -> bool {
-        let schema_lock = self.tables.read().await;
-        let table = schema_lock.get(table_name);
-        if table.is_none() {
-            return false;
+    /// Only superusers grant on all tables, owners may also grant on their own tables
+    pub async fn check_grant_option(
+        &self,
+        user: &str,
+        table_name: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        match table_name {
+            Some(table_name) => self.check_owner(user, table_name).await,
+            None if self.users.is_superuser(user).await => Ok(()),
+            None => Err(DatabaseError::PermissionDenied),
         }
-        let table = table.unwrap();
-        table.attributes[attr_pos].attribute_type == AttributeType::Id
     }
 
-    fn is_eq_comparison(comp: &Comparison) -> bool {
-        if let Comparison::Equal(_) = comp {
-            return true;
+    /// Only the owner of a table and superusers change its definition
+    pub async fn check_owner(&self, user: &str, table_name: &str) -> Result<(), DatabaseError> {
+        if self.users.is_superuser(user).await {
+            return Ok(());
+        }
+        match self.tables.read().await.get(table_name) {
+            Some(table) if table.owner == user => Ok(()),
+            _ => Err(DatabaseError::PermissionDenied),
         }
-        false
     }
 
-    pub async fn delete(
+    pub async fn grant(
         &self,
-        table_name: &str,
-        attr_pos: usize,
-        comparison: &Comparison,
+        user: &str,
+        table_name: Option<&str>,
+        privileges: &[Privilege],
     ) -> Result<DatabaseResponse, DatabaseError> {
-        let is_id = self.is_attr_id(table_name, attr_pos).await;
-        let read_lock = self.data.read().await;
-        let db_data = read_lock.get(table_name);
-        if db_data.is_none() {
-            return Err(DatabaseError::TableDoesNotExist);
-        }
-        let db_data = db_data.unwrap();
-        if is_id && Self::is_eq_comparison(comparison) {
-            if let Comparison::Equal(DataAttribute::Id(id)) = comparison {
-                db_data.delete_id(*id).await;
-            } else {
-                panic!("DataAttribute was not Id");
+        match table_name {
+            Some(table_name) => {
+                if !self.users.exists(user).await {
+                    return Err(DatabaseError::UserDoesNotExist);
+                }
+                let mut tables = self.tables.write().await;
+                let table = tables.get_mut(table_name).ok_or(DatabaseError::TableDoesNotExist)?;
+                table.grants.entry(user.to_string()).or_default().extend(privileges);
             }
-        } else {
-            db_data.delete(attr_pos, comparison).await;
+            None => self.users.grant(user, privileges).await?,
         }
         Ok(DatabaseResponse::Nothing)
     }
 
-    pub async fn select(
+    pub async fn revoke(
         &self,
-        table_name: &str,
-        attr_pos: usize,
-        comparison: &Comparison,
-        selected: Vec<usize>,
+        user: &str,
+        table_name: Option<&str>,
+        privileges: &[Privilege],
     ) -> Result<DatabaseResponse, DatabaseError> {
-        let is_id = self.is_attr_id(table_name, attr_pos).await;
-        let read_lock = self.data.read().await;
-        let db_data = read_lock.get(table_name);
-        if db_data.is_none() {
-            return Err(DatabaseError::TableDoesNotExist);
+        match table_name {
+            Some(table_name) => {
+                if !self.users.exists(user).await {
+                    return Err(DatabaseError::UserDoesNotExist);
+                }
+                let mut tables = self.tables.write().await;
+                let table = tables.get_mut(table_name).ok_or(DatabaseError::TableDoesNotExist)?;
+                if let Some(grants) = table.grants.get_mut(user) {
+                    grants.retain(|privilege| !privileges.contains(privilege));
+                }
+            }
+            None => self.users.revoke(user, privileges).await?,
         }
-        let db_data = db_data.unwrap();
-        if is_id && Self::is_eq_comparison(comparison) {
-            if let Comparison::Equal(DataAttribute::Id(id)) = comparison {
-                return Ok(DatabaseResponse::Data(db_data.get_by_id(*id, selected).await));
-            } else {
-                panic!("DataAttribute was not Id");
+        Ok(DatabaseResponse::Nothing)
+    }
+
+    /// Writes every table to `dir`, replacing the previous contents of the directory
+    pub async fn flush(&self, dir: &Path) -> io::Result<FlushSummary> {
+        let (db_tables, db_data) = self.read_catalog().await;
+        let tmp_dir = dir.with_extension("tmp");
+        if tmp_dir.exists() {
+            remove_dir_all(&tmp_dir)?;
+        }
+        create_dir_all(&tmp_dir)?;
+        let mut summary = FlushSummary::default();
+        // Only committed rows are written
+        let tx = self.begin();
+        for (name, table) in db_tables.iter() {
+            let table_data = &db_data[name];
+            let mut rows = BTreeMap::new();
+            for chunk in table_data.chunks.iter() {
+                rows.append(&mut chunk.records(&tx.view).await);
             }
+            summary.tables += 1;
+            summary.rows += rows.len();
+            let snapshot = TableSnapshot {
+                attributes: table.attributes.clone(),
+                owner: table.owner.clone(),
+                grants: table.grants.clone(),
+                counter: table_data.counter.get(),
+                rows,
+            };
+            let file = BufWriter::new(File::create(tmp_dir.join(table_file(name)))?);
+            serde_json::to_writer(file, &snapshot)?;
+        }
+        // Owners and grants refer to the users by name, so they are kept together
+        let file = BufWriter::new(File::create(tmp_dir.join(USERS_FILE))?);
+        serde_json::to_writer(file, &self.users.snapshot().await)?;
+        // The old snapshot is only removed once the new one is in place, `load` falls back to it
+        // when the process stops in between
+        let old_dir = dir.with_extension("old");
+        if dir.exists() {
+            if old_dir.exists() {
+                remove_dir_all(&old_dir)?;
+            }
+            rename(dir, &old_dir)?;
+        }
+        rename(&tmp_dir, dir)?;
+        if old_dir.exists() {
+            remove_dir_all(&old_dir)?;
         }
-        Ok(DatabaseResponse::Data(db_data.get(attr_pos, comparison, selected).await))
+        Ok(summary)
     }
 
-    pub async fn drop_table(&self, table_name: &str) -> Result<DatabaseResponse, DatabaseError> {
-        self.tables.write().await.remove(table_name);
-        self.data.write().await.remove(table_name);
-        Ok(DatabaseResponse::Nothing)
+    /// Loads the tables written by `flush`, a missing directory means an empty database
+    pub async fn load(&self, dir: &Path) -> io::Result<FlushSummary> {
+        let mut summary = FlushSummary::default();
+        let old_dir = dir.with_extension("old");
+        let dir = match (dir.exists(), old_dir.exists()) {
+            (true, _) => dir,
+            (false, true) => &old_dir,
+            (false, false) => return Ok(summary),
+        };
+        let (mut db_tables, mut db_data) = self.write_catalog().await;
+        let users = dir.join(USERS_FILE);
+        if users.exists() {
+            self.users.restore(serde_json::from_reader(BufReader::new(File::open(users)?))?).await;
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = table_name(&path) else {
+                continue;
+            };
+            let snapshot: TableSnapshot =
+                serde_json::from_reader(BufReader::new(File::open(&path)?))?;
+            let table_data = TableData {
+                chunks: new_tabledata(),
+                counter: RelaxedCounter::new(snapshot.counter),
+            };
+            summary.tables += 1;
+            summary.rows += snapshot.rows.len();
+            for (id, row) in snapshot.rows {
+                table_data.chunks[hash_id(id)].add(id, RowVersion::new(FROZEN_TX, row)).await;
+            }
+            let table = DatabaseTable {
+                attributes: snapshot.attributes,
+                counter: RelaxedCounter::new(0),
+                owner: snapshot.owner,
+                grants: snapshot.grants,
+            };
+            db_tables.insert(name.clone(), table);
+            db_data.insert(name, table_data);
+        }
+        Ok(summary)
     }
 
-    pub async fn tables(&self) -> Result<DatabaseResponse, DatabaseError> {
-        let tables = self.tables.read().await.keys().cloned().collect();
-        Ok(DatabaseResponse::Names(tables))
+    /// Names of the tables the user may select from
+    pub async fn tables(&self, user: &str) -> Result<DatabaseResponse, DatabaseError> {
+        let all = self.users.has_global_privilege(user, Privilege::Select).await;
+        let tables = self.tables.read().await;
+        let visible =
+            tables.iter().filter(|(_, table)| all || table.has_privilege(user, Privilege::Select));
+        Ok(DatabaseResponse::Names(visible.map(|(name, _)| name.clone()).collect()))
     }
 
     pub async fn table_attributes(&self, name: &str) -> Result<DatabaseResponse, DatabaseError> {
@@ -404,34 +1779,44 @@ impl Database {
 
 #[cfg(test)]
 mod tests {
+    use crate::database::DatabaseResponse::Id;
     use crate::database::{DataAttribute, DataAttributes, DatabaseResponse};
 
-    use super::{Attribute, AttributeType, Comparison, Database};
+    use super::{
+        AlterTable, Attribute, AttributeType, Change, Comparison, Database, Decimal, Float,
+        OnConflict, SetValue,
+    };
+    use crate::auth::Privilege;
+    use crate::error::DatabaseError;
+    use crate::error::DatabaseError::{
+        ColumnDoesNotExist, ColumnExists, NotNullViolation, PrimaryKeyColumn,
+    };
+    use crate::transaction::{RowVersion, StoredRow, Transaction};
 
     use rand::distributions::Alphanumeric;
     use rand::prelude::*;
     use rand::Rng;
     use rand_pcg::{Lcg128Xsl64, Pcg64};
+    use std::cmp::Ordering;
+    use std::fs::{remove_dir_all, rename};
 
     async fn fill_db() -> Database {
         let db = Database::default();
-        let mut attributes = vec![];
-        attributes.push(Attribute { name: "id".to_string(), attribute_type: AttributeType::Id });
-        attributes
-            .push(Attribute { name: "name".to_string(), attribute_type: AttributeType::String });
-        attributes
-            .push(Attribute { name: "age".to_string(), attribute_type: AttributeType::Number });
-        attributes.push(Attribute {
-            name: "lotto_numbers".to_string(),
-            attribute_type: AttributeType::Data,
-        });
-        assert!(db.create_table("people", attributes).await.is_ok());
+        let attributes = vec![
+            Attribute::new("id", AttributeType::Id),
+            Attribute::new("name", AttributeType::String),
+            Attribute::new("age", AttributeType::Number),
+            Attribute::new("lotto_numbers", AttributeType::Data),
+        ];
+        assert!(db.create_table("people", attributes, "admin").await.is_ok());
         let mut add_data = DataAttributes::default();
         add_data.attributes.push(DataAttribute::NoneId);
         add_data.attributes.push(DataAttribute::String("John Smith".to_string()));
         add_data.attributes.push(DataAttribute::Number(32));
         add_data.attributes.push(DataAttribute::Data(vec![1, 2, 3]));
-        assert!(db.insert("people", add_data).await.is_ok());
+        let mut tx = db.begin();
+        assert!(db.insert(&mut tx, "people", add_data).await.is_ok());
+        db.commit(tx).await;
         db
     }
 
@@ -443,11 +1828,12 @@ mod tests {
     #[tokio::test]
     async fn delete_data() {
         let db = fill_db().await;
+        let mut tx = db.begin();
         let attribute = DataAttribute::String("John Smith".to_string());
-        assert!(db.delete("people", 1, &Comparison::Equal(attribute)).await.is_ok());
+        assert!(db.delete(&mut tx, "people", 1, &Comparison::Equal(attribute)).await.is_ok());
         let attribute = DataAttribute::Id(0);
         let selected = vec![0];
-        let res = db.select("people", 0, &Comparison::Equal(attribute), selected).await;
+        let res = db.select(&tx, "people", 0, &Comparison::Equal(attribute), selected, None).await;
         assert!(res.is_ok());
         let res = res.unwrap();
         let expected_res = DatabaseResponse::Data(vec![]);
@@ -457,16 +1843,18 @@ mod tests {
     #[tokio::test]
     async fn get_id() {
         let db = fill_db().await;
+        let tx = db.begin();
         let attribute = DataAttribute::Id(0);
         let selected = vec![0, 1, 2, 3];
-        let res = db.select("people", 0, &Comparison::Equal(attribute), selected).await;
+        let res = db.select(&tx, "people", 0, &Comparison::Equal(attribute), selected, None).await;
         assert!(res.is_ok());
         let res = res.unwrap();
-        let mut attrs = vec![];
-        attrs.push(DataAttribute::Id(0));
-        attrs.push(DataAttribute::String("John Smith".to_string()));
-        attrs.push(DataAttribute::Number(32));
-        attrs.push(DataAttribute::Data(vec![1, 2, 3]));
+        let attrs = vec![
+            DataAttribute::Id(0),
+            DataAttribute::String("John Smith".to_string()),
+            DataAttribute::Number(32),
+            DataAttribute::Data(vec![1, 2, 3]),
+        ];
         let attrs = DataAttributes { attributes: attrs };
         let db_response = DatabaseResponse::Data(vec![attrs]);
         assert_eq!(res, db_response);
@@ -476,7 +1864,7 @@ mod tests {
     async fn get_all() {
         let db = fill_db().await;
         let selected = vec![1, 2, 3];
-        let res = db.select("people", 0, &Comparison::All, selected).await;
+        let res = db.select(&db.begin(), "people", 0, &Comparison::All, selected, None).await;
         assert!(res.is_ok());
         let res = res.unwrap();
         if let DatabaseResponse::Data(data) = res {
@@ -495,11 +1883,12 @@ mod tests {
         add_data.attributes.push(DataAttribute::String("".to_string()));
         add_data.attributes.push(DataAttribute::Number(123));
         add_data.attributes.push(DataAttribute::Data(vec![9, 2, 1]));
-        assert!(db.insert("people", add_data).await.is_ok());
+        let mut tx = db.begin();
+        assert!(db.insert(&mut tx, "people", add_data).await.is_ok());
         let selected = vec![1, 2, 3];
         let empty_data_string = DataAttribute::String("".to_string());
         let res = db
-            .select("people", 1, &Comparison::Equal(empty_data_string), selected)
+            .select(&tx, "people", 1, &Comparison::Equal(empty_data_string), selected, None)
             .await
             .expect("Select by empty string failed");
         if let DatabaseResponse::Data(data) = res {
@@ -510,6 +1899,621 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn flush_and_load() {
+        let db = fill_db().await;
+        db.users.create_user("alice", "secret", false).await.unwrap();
+        db.users.grant("alice", &[Privilege::Select]).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("minisql_flush_{}", std::process::id()));
+        let summary = db.flush(&dir).await.unwrap();
+        assert_eq!(summary, super::FlushSummary { tables: 1, rows: 1 });
+
+        let loaded = Database::default();
+        assert_eq!(loaded.load(&dir).await.unwrap(), summary);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(loaded.users.authenticate("alice", "secret").await.is_ok());
+        assert!(loaded.users.has_global_privilege("alice", Privilege::Select).await);
+        let selected = vec![0, 1, 2, 3];
+        let (tx, mut loaded_tx) = (db.begin(), loaded.begin());
+        let original =
+            db.select(&tx, "people", 0, &Comparison::All, selected.clone(), None).await.unwrap();
+        let res =
+            loaded.select(&loaded_tx, "people", 0, &Comparison::All, selected, None).await.unwrap();
+        assert_eq!(original, res);
+        // Ids continue after the loaded rows
+        let id = loaded.insert(&mut loaded_tx, "people", DataAttributes::default()).await.unwrap();
+        assert_eq!(id, DatabaseResponse::Id(1));
+    }
+
+    #[tokio::test]
+    async fn flush_any_table_name() {
+        let db = Database::default();
+        let names = ["a/../b", "", "x\0y", "ütf"];
+        for name in names {
+            let attributes = vec![Attribute::new("id", AttributeType::Id)];
+            db.create_table(name, attributes, "admin").await.unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("minisql_names_{}", std::process::id()));
+        db.flush(&dir).await.unwrap();
+        db.flush(&dir).await.unwrap();
+        assert!(!dir.with_extension("old").exists());
+
+        // Stopped after moving the previous snapshot aside
+        rename(&dir, dir.with_extension("old")).unwrap();
+        let loaded = Database::default();
+        assert_eq!(loaded.load(&dir).await.unwrap().tables, names.len());
+        remove_dir_all(dir.with_extension("old")).unwrap();
+        let loaded = loaded.tables.read().await;
+        assert!(names.iter().all(|name| loaded.contains_key(*name)));
+    }
+
+    fn person(name: &str) -> DataAttributes {
+        let attributes = vec![
+            DataAttribute::NoneId,
+            DataAttribute::String(name.to_string()),
+            DataAttribute::Number(0),
+            DataAttribute::Data(vec![]),
+        ];
+        DataAttributes { attributes }
+    }
+
+    async fn count(db: &Database, tx: &Transaction) -> usize {
+        match db.select(tx, "people", 0, &Comparison::All, vec![0], None).await.unwrap() {
+            DatabaseResponse::Data(data) => data.len(),
+            _ => panic!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn commit_and_rollback() {
+        let db = fill_db().await;
+        let mut first = db.begin();
+        let second = db.begin();
+        db.insert(&mut first, "people", person("Jane Doe")).await.unwrap();
+        let everyone = Comparison::All;
+        db.delete(&mut first, "people", 0, &everyone).await.unwrap();
+        db.insert(&mut first, "people", person("Jane Doe")).await.unwrap();
+        assert_eq!(count(&db, &first).await, 1);
+        // Uncommitted changes are not visible to other transactions
+        assert_eq!(count(&db, &second).await, 1);
+        db.rollback(first).await;
+        assert_eq!(count(&db, &second).await, 1);
+
+        let mut third = db.begin();
+        db.delete(&mut third, "people", 0, &everyone).await.unwrap();
+        db.insert(&mut third, "people", person("Jane Doe")).await.unwrap();
+        db.insert(&mut third, "people", person("Max Mustermann")).await.unwrap();
+        db.commit(third).await;
+        // The second transaction keeps reading its snapshot
+        assert_eq!(count(&db, &second).await, 1);
+        let tx = db.begin();
+        assert_eq!(count(&db, &tx).await, 2);
+        let name = Comparison::Equal(DataAttribute::String("John Smith".to_string()));
+        let res = db.select(&tx, "people", 1, &name, vec![0], None).await.unwrap();
+        assert_eq!(res, DatabaseResponse::Data(vec![]));
+    }
+
+    #[tokio::test]
+    async fn delete_conflict() {
+        let db = fill_db().await;
+        let (mut first, mut second) = (db.begin(), db.begin());
+        db.delete(&mut first, "people", 0, &Comparison::All).await.unwrap();
+        let res = db.delete(&mut second, "people", 0, &Comparison::All).await;
+        assert!(matches!(res, Err(DatabaseError::DataConflict)));
+        db.rollback(first).await;
+        db.delete(&mut second, "people", 0, &Comparison::All).await.unwrap();
+        db.commit(second).await;
+        assert_eq!(count(&db, &db.begin()).await, 0);
+    }
+
+    async fn stored_versions(db: &Database) -> usize {
+        let data = db.data.read().await;
+        let mut versions = 0;
+        for chunk in data["people"].chunks.iter() {
+            for row in chunk.lock_data().await.values() {
+                let row = RowVersion::from(row.clone());
+                let mut version = Some(&row);
+                while let Some(current) = version {
+                    versions += 1;
+                    version = current.previous.as_deref();
+                }
+            }
+        }
+        versions
+    }
+
+    async fn all_frozen(db: &Database) -> bool {
+        let data = db.data.read().await;
+        for chunk in data["people"].chunks.iter() {
+            if chunk.lock_data().await.values().any(|row| !matches!(row, StoredRow::Frozen(_))) {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[tokio::test]
+    async fn snapshot_and_garbage() {
+        let db = fill_db().await;
+        let mut reader = db.begin();
+        let mut writer = db.begin();
+        db.delete(&mut writer, "people", 0, &Comparison::All).await.unwrap();
+        db.insert(&mut writer, "people", person("Jane Doe")).await.unwrap();
+        db.commit(writer).await;
+        assert_eq!(count(&db, &reader).await, 1);
+        let name = Comparison::Equal(DataAttribute::String("John Smith".to_string()));
+        let res = db.select(&reader, "people", 1, &name, vec![0], None).await.unwrap();
+        assert_eq!(
+            res,
+            DatabaseResponse::Data(vec![DataAttributes { attributes: vec![DataAttribute::Id(0)] }])
+        );
+        // The row was deleted after the snapshot was taken
+        let res = db.delete(&mut reader, "people", 0, &Comparison::All).await;
+        assert!(matches!(res, Err(DatabaseError::DataConflict)));
+
+        // Old versions stay until no snapshot needs them
+        db.collect_garbage().await;
+        assert_eq!(stored_versions(&db).await, 2);
+        db.rollback(reader).await;
+        assert!(db.collect_garbage().await > 0);
+        assert_eq!(stored_versions(&db).await, 1);
+        assert_eq!(count(&db, &db.begin()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn not_null() {
+        let db = Database::default();
+        let name = Attribute { not_null: true, ..Attribute::new("name", AttributeType::String) };
+        let attributes = vec![Attribute::new("id", AttributeType::Id), name];
+        db.create_table("t", attributes, "admin").await.unwrap();
+        let mut tx = db.begin();
+        let row = |name| DataAttributes { attributes: vec![DataAttribute::NoneId, name] };
+        let inserted = db.insert(&mut tx, "t", row(DataAttribute::None)).await;
+        assert!(matches!(inserted, Err(NotNullViolation(column)) if column == "name"));
+        assert!(tx.writes.is_empty());
+        let name = DataAttribute::String(String::new());
+        assert!(db.insert(&mut tx, "t", row(name)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn upsert_not_null() {
+        let db = Database::default();
+        let b = Attribute { not_null: true, ..Attribute::new("b", AttributeType::Number) };
+        let attributes = vec![
+            Attribute::new("id", AttributeType::Id),
+            Attribute::new("a", AttributeType::Number),
+            b,
+        ];
+        db.create_table("t", attributes, "admin").await.unwrap();
+        let row = |a, b| DataAttributes {
+            attributes: vec![DataAttribute::Id(1), a, DataAttribute::Number(b)],
+        };
+        let mut tx = db.begin();
+        db.insert(&mut tx, "t", row(DataAttribute::Number(1), 1)).await.unwrap();
+        // A NULL taken from the proposed row cannot end up in the NOT NULL column
+        let update = OnConflict::Update(vec![(2, SetValue::Excluded(1))]);
+        let upserted =
+            db.insert_rows(&mut tx, "t", vec![row(DataAttribute::None, 5)], &update, None);
+        assert!(matches!(upserted.await, Err(NotNullViolation(column)) if column == "b"));
+        let all = db.select(&tx, "t", 0, &Comparison::All, vec![2], None).await.unwrap();
+        let b = DataAttributes { attributes: vec![DataAttribute::Number(1)] };
+        assert_eq!(all, DatabaseResponse::Data(vec![b]));
+        let upserted =
+            db.insert_rows(&mut tx, "t", vec![row(DataAttribute::Number(7), 5)], &update, None);
+        assert_eq!(upserted.await.unwrap(), Id(1));
+    }
+
+    #[tokio::test]
+    async fn insert_rows() {
+        let db = fill_db().await;
+        let mut tx = db.begin();
+        let rows = vec![person("a"), person("b"), person("c")];
+        let inserted =
+            db.insert_rows(&mut tx, "people", rows, &OnConflict::Error, None).await.unwrap();
+        assert_eq!(inserted, DatabaseResponse::Ids(vec![1, 2, 3]));
+        assert_eq!(count(&db, &tx).await, 4);
+        assert_eq!(tx.writes.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn explicit_ids() {
+        let db = fill_db().await;
+        let with_id = |id, name| {
+            let mut row = person(name);
+            row.attributes[0] = DataAttribute::Id(id);
+            row
+        };
+        let mut tx = db.begin();
+        assert_eq!(db.insert(&mut tx, "people", with_id(3, "a")).await.unwrap(), Id(3));
+        let taken = db.insert(&mut tx, "people", with_id(0, "b")).await;
+        assert!(matches!(taken, Err(DatabaseError::DataConflict)));
+        // Generated ids continue after the explicit one
+        assert_eq!(db.insert(&mut tx, "people", person("c")).await.unwrap(), Id(4));
+        db.rollback(tx).await;
+
+        let mut tx = db.begin();
+        assert_eq!(db.insert(&mut tx, "people", with_id(8, "d")).await.unwrap(), Id(8));
+        let mut other = db.begin();
+        let taken = db.insert(&mut other, "people", with_id(8, "e")).await;
+        assert!(matches!(taken, Err(DatabaseError::DataConflict)));
+        db.rollback(tx).await;
+        // The id of a rolled back row is free again
+        assert_eq!(db.insert(&mut other, "people", with_id(8, "e")).await.unwrap(), Id(8));
+        let selected =
+            db.select(&other, "people", 0, &Comparison::Equal(DataAttribute::Id(8)), vec![1], None);
+        let name = DataAttributes { attributes: vec![DataAttribute::String("e".to_string())] };
+        assert_eq!(selected.await.unwrap(), DatabaseResponse::Data(vec![name]));
+    }
+
+    #[tokio::test]
+    async fn explicit_id_limits() {
+        let db = fill_db().await;
+        let with_id = |id| {
+            let mut row = person("a");
+            row.attributes[0] = DataAttribute::Id(id);
+            row
+        };
+        let mut tx = db.begin();
+        let largest = db.insert(&mut tx, "people", with_id(i64::MAX)).await;
+        assert!(matches!(largest, Err(DatabaseError::IdOutOfRange)));
+        assert_eq!(
+            db.insert(&mut tx, "people", with_id(i64::MAX - 1)).await.unwrap(),
+            Id(i64::MAX - 1)
+        );
+        db.rollback(tx).await;
+
+        // A row rejected as a duplicate does not move the generated ids
+        let db = fill_db().await;
+        let row = RowVersion::new(crate::transaction::FROZEN_TX, with_id(50));
+        db.data.read().await["people"].chunks[super::hash_id(50)].add(50, row).await;
+        let mut tx = db.begin();
+        let taken = db.insert(&mut tx, "people", with_id(50)).await;
+        assert!(matches!(taken, Err(DatabaseError::DataConflict)));
+        assert_eq!(db.insert(&mut tx, "people", person("b")).await.unwrap(), Id(1));
+    }
+
+    async fn name_and_age(db: &Database, tx: &Transaction) -> DatabaseResponse {
+        let id = Comparison::Equal(DataAttribute::Id(0));
+        db.select(tx, "people", 0, &id, vec![1, 2], None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn upsert() {
+        let db = fill_db().await;
+        let john = |age| {
+            let mut row = person("John Doe");
+            row.attributes[0] = DataAttribute::Id(0);
+            row.attributes[2] = DataAttribute::Number(age);
+            row
+        };
+        let row = |name: &str, age| {
+            DatabaseResponse::Data(vec![DataAttributes {
+                attributes: vec![
+                    DataAttribute::String(name.to_string()),
+                    DataAttribute::Number(age),
+                ],
+            }])
+        };
+        let old = db.begin();
+        let mut tx = db.begin();
+        let ignored = db.insert_rows(&mut tx, "people", vec![john(40)], &OnConflict::Nothing, None);
+        assert_eq!(ignored.await.unwrap(), DatabaseResponse::Ids(vec![]));
+        assert_eq!(name_and_age(&db, &tx).await, row("John Smith", 32));
+        let set = vec![(2, SetValue::Excluded(2)), (1, SetValue::Value(DataAttribute::None))];
+        let update = OnConflict::Update(set);
+        let updated = db.insert_rows(&mut tx, "people", vec![john(40)], &update, None).await;
+        assert_eq!(updated.unwrap(), Id(0));
+        assert_eq!(
+            name_and_age(&db, &tx).await,
+            DatabaseResponse::Data(vec![DataAttributes {
+                attributes: vec![DataAttribute::None, DataAttribute::Number(40)],
+            }])
+        );
+        let set = vec![(1, SetValue::Value(DataAttribute::String("Jane Doe".to_string())))];
+        let update = OnConflict::Update(set);
+        db.insert_rows(&mut tx, "people", vec![john(50)], &update, None).await.unwrap();
+        assert_eq!(name_and_age(&db, &tx).await, row("Jane Doe", 40));
+        assert_eq!(count(&db, &tx).await, 1);
+
+        // Another transaction cannot update the row before the update is finished
+        let mut other = db.begin();
+        let conflict = db.insert_rows(&mut other, "people", vec![john(60)], &update, None).await;
+        assert!(matches!(conflict, Err(DatabaseError::DataConflict)));
+        db.rollback(other).await;
+        db.commit(tx).await;
+        // Snapshots taken before the commit still see the old version
+        assert_eq!(name_and_age(&db, &old).await, row("John Smith", 32));
+        let mut tx = db.begin();
+        assert_eq!(name_and_age(&db, &tx).await, row("Jane Doe", 40));
+        db.insert_rows(&mut tx, "people", vec![john(70)], &update, None).await.unwrap();
+        db.rollback(tx).await;
+        db.commit(old).await;
+        db.collect_garbage().await;
+        let tx = db.begin();
+        assert_eq!(name_and_age(&db, &tx).await, row("Jane Doe", 40));
+        assert_eq!(count(&db, &tx).await, 1);
+        // Once nobody needs the replaced versions the rows cost no more than before versioning
+        assert!(all_frozen(&db).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_upserts() {
+        let db = std::sync::Arc::new(fill_db().await);
+        let mut tasks = vec![];
+        for task in 0..4 {
+            let db = db.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..100 {
+                    let mut row = person("x");
+                    row.attributes[0] = DataAttribute::Id(1 + i % 5);
+                    row.attributes[2] = DataAttribute::Number(task);
+                    let update = OnConflict::Update(vec![(2, SetValue::Excluded(2))]);
+                    let on_conflict = if i % 2 == 0 { &update } else { &OnConflict::Nothing };
+                    let mut tx = db.begin();
+                    match db.insert_rows(&mut tx, "people", vec![row], on_conflict, None).await {
+                        Ok(_) => db.commit(tx).await,
+                        Err(_) => db.rollback(tx).await,
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        db.collect_garbage().await;
+        assert!(all_frozen(&db).await);
+        let tx = db.begin();
+        let ids = db.select(&tx, "people", 0, &Comparison::All, vec![0], None).await.unwrap();
+        let DatabaseResponse::Data(mut ids) = ids else { panic!() };
+        ids.sort_by(|a, b| a.attributes[0].partial_cmp(&b.attributes[0]).unwrap());
+        let expected = (0..6)
+            .map(|id| DataAttributes { attributes: vec![DataAttribute::Id(id)] })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, expected);
+    }
+
+    async fn count_aged(db: &Database, tx: &Transaction, age: i64) -> usize {
+        let age = Comparison::Equal(DataAttribute::Number(age));
+        match db.select(tx, "people", 2, &age, vec![0], None).await.unwrap() {
+            DatabaseResponse::Data(data) => data.len(),
+            _ => panic!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn update() {
+        let db = fill_db().await;
+        let mut tx = db.begin();
+        db.insert(&mut tx, "people", person("Jane Doe")).await.unwrap();
+        db.commit(tx).await;
+        let old = db.begin();
+        let mut tx = db.begin();
+        let set = vec![(2, SetValue::Value(DataAttribute::Number(40)))];
+        let everyone = Comparison::Higher(DataAttribute::Number(-1));
+        let updated = db.update(&mut tx, "people", set.clone(), 2, &everyone).await;
+        assert_eq!(updated.unwrap(), DatabaseResponse::Count(2));
+        assert_eq!(tx.writes.len(), 4);
+        assert_eq!(count_aged(&db, &tx, 40).await, 2);
+        // Another transaction cannot change the rows before the update is finished
+        let mut other = db.begin();
+        let conflict = db.update(&mut other, "people", set.clone(), 0, &Comparison::All).await;
+        assert!(matches!(conflict, Err(DatabaseError::DataConflict)));
+        db.rollback(other).await;
+        let null = vec![(1, SetValue::Value(DataAttribute::None))];
+        db.update(&mut tx, "people", null, 0, &Comparison::All).await.unwrap();
+        db.commit(tx).await;
+        // Snapshots taken before the commit still see the old versions
+        assert_eq!(count_aged(&db, &old, 40).await, 0);
+        assert_eq!(count(&db, &old).await, 2);
+        drop(old);
+        db.collect_garbage().await;
+        assert_eq!(stored_versions(&db).await, 2);
+
+        let mut tx = db.begin();
+        let nobody = Comparison::Equal(DataAttribute::Number(1));
+        let updated = db.update(&mut tx, "people", set, 2, &nobody).await;
+        assert_eq!(updated.unwrap(), DatabaseResponse::Count(0));
+        let deleted = db.delete(&mut tx, "people", 0, &Comparison::Equal(DataAttribute::Id(1)));
+        assert_eq!(deleted.await.unwrap(), DatabaseResponse::Count(1));
+        db.rollback(tx).await;
+        let tx = db.begin();
+        assert_eq!(count_aged(&db, &tx, 40).await, 2);
+    }
+
+    #[tokio::test]
+    async fn returning() {
+        let db = fill_db().await;
+        let mut tx = db.begin();
+        let rows = vec![person("a"), person("b")];
+        let inserted =
+            db.insert_rows(&mut tx, "people", rows, &OnConflict::Error, Some(vec![0, 1]));
+        let row = |id, name: &str| DataAttributes {
+            attributes: vec![DataAttribute::Id(id), DataAttribute::String(name.to_string())],
+        };
+        assert_eq!(inserted.await.unwrap(), DatabaseResponse::Data(vec![row(1, "a"), row(2, "b")]));
+        // Updates return the new versions and deletes the deleted ones
+        let set = vec![(1, SetValue::Value(DataAttribute::String("c".to_string())))];
+        let id = Comparison::Equal(DataAttribute::Id(1));
+        let updated = db.change(&mut tx, "people", 0, &id, Change::Update(set), Some(vec![0, 1]));
+        assert_eq!(updated.await.unwrap(), DatabaseResponse::Data(vec![row(1, "c")]));
+        let deleted = db.change(&mut tx, "people", 0, &id, Change::Delete, Some(vec![1, 0]));
+        let name_and_id = DataAttributes {
+            attributes: vec![DataAttribute::String("c".to_string()), DataAttribute::Id(1)],
+        };
+        assert_eq!(deleted.await.unwrap(), DatabaseResponse::Data(vec![name_and_id]));
+    }
+
+    #[test]
+    fn real_order() {
+        let mut reals = [f64::NAN, 1.5, f64::INFINITY, -0.0, f64::NEG_INFINITY, 0.0].map(Float);
+        reals.sort();
+        let expected = [f64::NEG_INFINITY, 0.0, 0.0, 1.5, f64::INFINITY, f64::NAN].map(Float);
+        assert!(reals == expected);
+
+        let (one, real_one) = (DataAttribute::Number(1), DataAttribute::Real(Float(1.0)));
+        assert_eq!(one.compare(&real_one), Some(Ordering::Equal));
+        assert_eq!(DataAttribute::Real(Float(0.5)).compare(&one), Some(Ordering::Less));
+
+        // JSON can hold neither NaN nor the infinities
+        let stored = serde_json::to_string(&reals).unwrap();
+        assert_eq!(stored, r#"["-inf",-0.0,0.0,1.5,"inf","NaN"]"#);
+        let loaded: Vec<Float> = serde_json::from_str(&stored).unwrap();
+        assert!(loaded == reals);
+    }
+
+    #[test]
+    fn decimals() {
+        let decimal = |text: &str| text.parse::<Decimal>().unwrap();
+        for (text, shown) in [("12.50", "12.50"), ("-.05", "-0.05"), ("+7", "7"), ("1.5e3", "1500")]
+        {
+            assert_eq!(decimal(text).to_string(), shown);
+        }
+        for text in ["", ".", "1.2.3", "1e", "abc", "1e19", "1e-19", "--1"] {
+            assert!(text.parse::<Decimal>().is_err(), "{}", text);
+        }
+        assert_eq!(decimal("2.345").rescale(2).unwrap().to_string(), "2.35");
+        assert_eq!(decimal("-2.344").rescale(2).unwrap().to_string(), "-2.34");
+        assert_eq!(decimal("9.5").rescale(0).unwrap().to_string(), "10");
+        assert!(decimal("99.99").fits(4) && !decimal("99.99").rescale(3).unwrap().fits(4));
+
+        assert!(decimal("1.50") == decimal("1.5") && decimal("0.1") > decimal("0.09"));
+        let huge = decimal("999999999999999999");
+        assert!(huge > decimal("0.000000000000000001"));
+        assert!(decimal("-1e17") < decimal("0.1") && Decimal::from(i64::MIN) < decimal("-1"));
+        for (lhs, rhs, sum, difference, product) in [
+            ("1.5", "0.25", "1.75", "1.25", "0.375"),
+            ("-0.1", "0.10", "0.00", "-0.20", "-0.010"),
+            ("12", "-0.5", "11.5", "12.5", "-6.0"),
+        ] {
+            let (lhs, rhs) = (decimal(lhs), decimal(rhs));
+            assert_eq!(lhs.checked_add(&rhs).unwrap().to_string(), sum);
+            assert_eq!(lhs.checked_sub(&rhs).unwrap().to_string(), difference);
+            assert_eq!(lhs.checked_mul(&rhs).unwrap().to_string(), product);
+        }
+        // Places beyond the precision are rounded, digits beyond it overflow
+        let small = decimal("0.000000001");
+        assert_eq!(
+            small.checked_mul(&decimal("0.0000000015")).unwrap().to_string(),
+            "0.000000000000000002"
+        );
+        let overflow = huge.checked_add(&decimal("1"));
+        assert!(matches!(overflow, Err(DatabaseError::NumericOverflow)));
+        assert!(
+            huge.checked_sub(&decimal("0.1")).is_err() && huge.checked_mul(&decimal("10")).is_err()
+        );
+        assert_eq!(huge.checked_sub(&huge).unwrap().to_string(), "0");
+        let set = [decimal("1.50"), decimal("1.5000")].into_iter();
+        let set = set.collect::<std::collections::HashSet<_>>();
+        assert_eq!(set.len(), 1);
+
+        let (price, cents) = (DataAttribute::Decimal(decimal("0.10")), DataAttribute::Number(0));
+        assert_eq!(price.compare(&cents), Some(Ordering::Greater));
+        assert_eq!(DataAttribute::Real(Float(0.1)).compare(&price), Some(Ordering::Equal));
+        for (text, real) in [("-2.50", -2.5), ("1e-18", 1e-18), ("12345.678", 12345.678)] {
+            assert_eq!(decimal(text).to_f64(), real, "{}", text);
+        }
+        let tiny = DataAttribute::Decimal(decimal("0.000000000000000001"));
+        assert_eq!(tiny.compare(&DataAttribute::Real(Float(0.0))), Some(Ordering::Greater));
+        let stored = serde_json::to_string(&price).unwrap();
+        assert_eq!(stored, r#"{"Decimal":"0.10"}"#);
+        assert_eq!(serde_json::from_str::<DataAttribute>(&stored).unwrap(), price);
+    }
+
+    #[tokio::test]
+    async fn alter_table() {
+        let db = fill_db().await;
+        db.collect_garbage().await;
+        let mut tx = db.begin();
+        db.insert(&mut tx, "people", person("Jane Doe")).await.unwrap();
+        let attribute = Attribute {
+            default: Some(DataAttribute::String("Paris".to_string())),
+            ..Attribute::new("city", AttributeType::String)
+        };
+        db.alter_table("people", AlterTable::AddColumn { attribute }).await.unwrap();
+        let attribute =
+            Attribute { not_null: true, ..Attribute::new("zip", AttributeType::Number) };
+        let added = db.alter_table("people", AlterTable::AddColumn { attribute }).await;
+        assert!(matches!(added, Err(NotNullViolation(column)) if column == "zip"));
+        let drop = |name: &str| AlterTable::DropColumn { name: name.to_string() };
+        db.alter_table("people", drop("lotto_numbers")).await.unwrap();
+        assert!(matches!(db.alter_table("people", drop("id")).await, Err(PrimaryKeyColumn)));
+        assert!(matches!(db.alter_table("people", drop("nope")).await, Err(ColumnDoesNotExist)));
+        let rename = |name: &str, new_name: &str| AlterTable::RenameColumn {
+            name: name.to_string(),
+            new_name: new_name.to_string(),
+        };
+        assert!(matches!(db.alter_table("people", rename("age", "name")).await, Err(ColumnExists)));
+        db.alter_table("people", rename("age", "years")).await.unwrap();
+        let columns = ["id", "name", "years", "city"].map(String::from).to_vec();
+        assert_eq!(db.table_attributes("people").await.unwrap(), DatabaseResponse::Names(columns));
+        let rename_table = AlterTable::Rename { new_name: "persons".to_string() };
+        db.alter_table("people", rename_table).await.unwrap();
+
+        // The insert made before the rename is still finished on commit
+        db.commit(tx).await;
+        assert_eq!(db.collect_garbage().await, 1);
+        let tx = db.begin();
+        let cities =
+            db.select(&tx, "persons", 0, &Comparison::All, vec![1, 3], None).await.unwrap();
+        let DatabaseResponse::Data(mut rows) = cities else { panic!() };
+        rows.sort_by(|a, b| a.attributes[0].partial_cmp(&b.attributes[0]).unwrap());
+        let row = |name: &str| DataAttributes {
+            attributes: vec![
+                DataAttribute::String(name.to_string()),
+                DataAttribute::String("Paris".to_string()),
+            ],
+        };
+        assert_eq!(rows, vec![row("Jane Doe"), row("John Smith")]);
+        assert!(db.select(&tx, "people", 0, &Comparison::All, vec![0], None).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_create_and_drop() {
+        use DatabaseError::TableDoesNotExist;
+        let db = std::sync::Arc::new(fill_db().await);
+        let mut tasks = vec![];
+        let ddl = db.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..200 {
+                let attributes = vec![
+                    Attribute::new("id", AttributeType::Id),
+                    Attribute::new("name", AttributeType::String),
+                ];
+                ddl.create_table("t", attributes, "admin").await.unwrap();
+                ddl.drop_table("t").await.unwrap();
+            }
+        }));
+        for _ in 0..2 {
+            let dml = db.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..200 {
+                    let mut tx = dml.begin();
+                    let data = DataAttributes {
+                        attributes: vec![DataAttribute::NoneId, DataAttribute::String("x".into())],
+                    };
+                    // The table may be gone at any point, but nothing else can fail
+                    let inserted = dml.insert(&mut tx, "t", data).await;
+                    assert!(matches!(inserted, Ok(_) | Err(TableDoesNotExist)), "{:?}", inserted);
+                    let name = Comparison::Equal(DataAttribute::String("x".to_string()));
+                    let selected = dml.select(&tx, "t", 1, &name, vec![0, 1], None).await;
+                    assert!(matches!(selected, Ok(_) | Err(TableDoesNotExist)), "{:?}", selected);
+                    dml.commit(tx).await;
+                }
+            }));
+        }
+        let checker = db.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..200 {
+                // A table is never visible with only its schema or only its data
+                let (schemas, data) = checker.read_catalog().await;
+                assert_eq!(schemas.contains_key("t"), data.contains_key("t"));
+            }
+        }));
+        for task in tasks {
+            task.await.unwrap();
+        }
+    }
+
     async fn add_random_entry(db: &mut Database, rng: &mut Lcg128Xsl64) -> DataAttributes {
         let mut add_data = DataAttributes::default();
         add_data.attributes.push(DataAttribute::NoneId);
@@ -521,7 +2525,10 @@ mod tests {
         add_data.attributes.push(DataAttribute::Number(num));
         add_data.attributes.push(DataAttribute::Data((0..64).map(|_| rng.gen()).collect()));
         let mut copied_data = add_data.clone();
-        let id = db.insert("people", add_data).await.unwrap();
+        let mut tx = db.begin();
+        let id = db.insert(&mut tx, "people", add_data).await.unwrap();
+        db.commit(tx).await;
+        db.collect_garbage().await;
         if let DatabaseResponse::Id(id) = id {
             copied_data.attributes[0] = DataAttribute::Id(id);
         }
@@ -531,15 +2538,16 @@ mod tests {
     async fn test_random_value(
         db: &mut Database,
         rng: &mut Lcg128Xsl64,
-        dataset: &Vec<DataAttributes>,
+        dataset: &[DataAttributes],
     ) {
         let index = rng.gen_range(0..dataset.len());
         let tested_value = &dataset[index];
         let selected = vec![0, 1, 2, 3];
+        let comparison = Comparison::Equal(tested_value.attributes[0].clone());
         let res = db
-            .select("people", 0, &Comparison::Equal(tested_value.attributes[0].clone()), selected)
+            .select(&db.begin(), "people", 0, &comparison, selected, None)
             .await
-            .expect(&format!("Expected data not found {:?}", tested_value));
+            .unwrap_or_else(|_| panic!("Expected data not found {:?}", tested_value));
         if let DatabaseResponse::Data(data) = res {
             assert_eq!(tested_value, &data[0]);
         } else {
@@ -554,9 +2562,12 @@ mod tests {
     ) {
         let index = rng.gen_range(0..dataset.len());
         let id = dataset[index].attributes[0].clone();
-        db.delete("people", 0, &Comparison::Equal(id.clone()))
+        let mut tx = db.begin();
+        db.delete(&mut tx, "people", 0, &Comparison::Equal(id.clone()))
             .await
-            .expect(&format!("Failed to delete id: {:?}", id));
+            .unwrap_or_else(|_| panic!("Failed to delete id: {:?}", id));
+        db.commit(tx).await;
+        db.collect_garbage().await;
         dataset.remove(index);
     }
 
@@ -7,6 +7,23 @@ pub enum DatabaseError {
     DataConflict,
     TableDoesNotExist,
     NoDataFound,
+    UserExists,
+    UserDoesNotExist,
+    UserOwnsTables,
+    AuthenticationRequired,
+    AuthenticationFailed,
+    InvalidPasswordHash,
+    PermissionDenied,
+    TransactionInProgress,
+    NoTransaction,
+    TransactionAborted,
+    SavepointDoesNotExist,
+    ColumnExists,
+    ColumnDoesNotExist,
+    PrimaryKeyColumn,
+    IdOutOfRange,
+    NumericOverflow,
+    NotNullViolation(String),
 }
 
 impl Display for DatabaseError {
@@ -16,6 +33,25 @@ impl Display for DatabaseError {
             DatabaseError::DataConflict => "Data Conflict",
             DatabaseError::TableDoesNotExist => "Table Does Not Exist",
             DatabaseError::NoDataFound => "No Data Found",
+            DatabaseError::UserExists => "User Already Exists",
+            DatabaseError::UserDoesNotExist => "User Does Not Exist",
+            DatabaseError::UserOwnsTables => "User Owns Tables",
+            DatabaseError::AuthenticationRequired => "Authentication Required",
+            DatabaseError::AuthenticationFailed => "Authentication Failed",
+            DatabaseError::InvalidPasswordHash => "Stored Password Hash Is Invalid",
+            DatabaseError::PermissionDenied => "Permission Denied",
+            DatabaseError::TransactionInProgress => "Transaction Already In Progress",
+            DatabaseError::NoTransaction => "No Transaction In Progress",
+            DatabaseError::TransactionAborted => "Transaction Aborted, Rollback Required",
+            DatabaseError::SavepointDoesNotExist => "Savepoint Does Not Exist",
+            DatabaseError::ColumnExists => "Column Already Exists",
+            DatabaseError::ColumnDoesNotExist => "Column Does Not Exist",
+            DatabaseError::PrimaryKeyColumn => "Primary Key Column Cannot Be Dropped",
+            DatabaseError::IdOutOfRange => "Id Out Of Range",
+            DatabaseError::NumericOverflow => "Numeric Value Out Of Range",
+            DatabaseError::NotNullViolation(column) => {
+                return write!(f, "Null Value In Column {} Violates Not Null Constraint", column)
+            }
         };
 
         f.write_str(message)
@@ -1,15 +1,16 @@
 use std::{collections::HashMap, io};
 
-use crate::database::DataAttribute;
+use crate::database::{Attribute, Collation, DataAttribute};
 use std::collections::HashSet;
-use std::fs::{create_dir_all, remove_dir_all, File, remove_file};
-use std::io::{BufReader, Error, ErrorKind};
+use std::fs::{create_dir_all, remove_dir_all, remove_file, File};
+use std::io::{BufReader, Error};
 use std::iter::FromIterator;
 use std::path::Path;
 
 pub async fn create_index(
     table_name: &str,
     attr_name: &str,
+    collation: Collation,
     id_vec: &[DataAttribute],
     attr_vec: &[DataAttribute],
 ) -> io::Result<String> {
@@ -21,8 +22,8 @@ pub async fn create_index(
     create_dir_all(path.parent().unwrap()).unwrap();
     let mut map: HashMap<String, Vec<DataAttribute>> = HashMap::new();
     for i in 0..id_vec.len() {
-        let key = format!("{:?}", attr_vec[i].clone());
-        map.entry(key).or_insert_with(Vec::new).push(id_vec[i].clone());
+        let key = index_key(&attr_vec[i], collation);
+        map.entry(key).or_default().push(id_vec[i].clone());
     }
 
     let file = File::create(file_path)?;
@@ -30,6 +31,15 @@ pub async fn create_index(
     Ok(String::from("Index successfully created!"))
 }
 
+/// Strings equal under the collation of the column share a key, so the index finds
+/// the same rows as a comparison of the column
+fn index_key(value: &DataAttribute, collation: Collation) -> String {
+    match value {
+        DataAttribute::String(text) => format!("{:?}", DataAttribute::String(collation.fold(text))),
+        _ => format!("{:?}", value),
+    }
+}
+
 pub async fn index_exists(table_name: &str, attr_name: &str) -> bool {
     let file_path = format!("./database/{}/{}", table_name, attr_name);
     let path = Path::new(&file_path);
@@ -39,13 +49,14 @@ pub async fn index_exists(table_name: &str, attr_name: &str) -> bool {
 pub async fn index_find(
     table_name: &str,
     attr_name: &str,
+    collation: Collation,
     item: &DataAttribute,
 ) -> io::Result<Vec<DataAttribute>> {
     if !index_exists(table_name, attr_name).await {
-        return Err(Error::new(ErrorKind::Other, "Index does not exist"));
+        return Err(Error::other("Index does not exist"));
     }
     let map = get_index_map(table_name, attr_name);
-    match map.get(&*format!("{:?}", item)) {
+    match map.get(&index_key(item, collation)) {
         Some(res) => Ok(res.clone()),
         None => Ok(vec![]),
     }
@@ -53,12 +64,13 @@ pub async fn index_find(
 
 pub async fn table_index_insert(
     table_name: &str,
-    attr_names: Vec<&String>,
+    attributes: &[Attribute],
     values: Vec<&DataAttribute>,
 ) -> io::Result<()> {
-    for i in 1..attr_names.len() {
-        if index_exists(table_name, attr_names[i]).await {
-            index_insert(table_name, attr_names[i], values[0], values[i]).await?;
+    for i in 1..attributes.len() {
+        let attribute = &attributes[i];
+        if index_exists(table_name, &attribute.name).await {
+            index_insert(table_name, attribute, values[0], values[i]).await?;
         }
     }
     Ok(())
@@ -66,14 +78,14 @@ pub async fn table_index_insert(
 
 async fn index_insert(
     table_name: &str,
-    attr_name: &str,
+    attribute: &Attribute,
     id: &DataAttribute,
     value: &DataAttribute,
 ) -> io::Result<()> {
-    let file_path = format!("./database/{}/{}", table_name, attr_name);
-    let mut map = get_index_map(table_name, attr_name);
-    let key = format!("{:?}", value.clone());
-    map.entry(key).or_insert_with(Vec::new).push(id.clone());
+    let file_path = format!("./database/{}/{}", table_name, attribute.name);
+    let mut map = get_index_map(table_name, &attribute.name);
+    let key = index_key(value, attribute.collation);
+    map.entry(key).or_default().push(id.clone());
     serde_json::to_writer(File::create(file_path)?, &map)?;
     Ok(())
 }
@@ -100,7 +112,7 @@ async fn index_delete(
     let file_path = format!("./database/{}/{}", table_name, attr_name);
     let mut map = get_index_map(table_name, attr_name);
 
-    map.values_mut().into_iter().for_each(|v| {
+    map.values_mut().for_each(|v| {
         v.retain(|x| !set.contains(x));
     });
 
@@ -128,6 +140,7 @@ pub fn get_index_map(table_name: &str, attr_name: &str) -> HashMap<String, Vec<D
 
 #[cfg(test)]
 mod tests {
+    use crate::database::AttributeType;
     use crate::database::DataAttribute::*;
     use crate::index::*;
     use std::string::String;
@@ -137,8 +150,9 @@ mod tests {
         assert!(create_index(
             &String::from("test123"),
             &String::from("second"),
-            &vec![Id(1)],
-            &vec![DataAttribute::String("smth".parse().unwrap())]
+            Collation::Binary,
+            &[Id(1)],
+            &[DataAttribute::String("smth".parse().unwrap())]
         )
         .await
         .is_ok());
@@ -157,6 +171,7 @@ mod tests {
         let res = index_find(
             &String::from("test123"),
             &String::from("second"),
+            Collation::Binary,
             &DataAttribute::String(String::from("nothing")),
         )
         .await;
@@ -172,6 +187,7 @@ mod tests {
         let res = index_find(
             &String::from("test123"),
             &String::from("second"),
+            Collation::Binary,
             &DataAttribute::String(String::from("smth")),
         )
         .await;
@@ -189,20 +205,20 @@ mod tests {
     }
 
     async fn test_table_index_insert() {
+        let attributes = [
+            Attribute::new("", AttributeType::Id),
+            Attribute::new("second", AttributeType::String),
+        ];
         assert!(table_index_insert(
-            &"test123".to_string(),
-            vec![&String::from(""), &String::from("second")],
+            "test123",
+            &attributes,
             vec![&Id(2), &String("test_ins".to_string())]
         )
         .await
         .is_ok());
 
-        match index_find(
-            &"test123".to_string(),
-            &String::from("second"),
-            &String(String::from("test_ins")),
-        )
-        .await
+        match index_find("test123", "second", Collation::Binary, &String(String::from("test_ins")))
+            .await
         {
             Ok(res) => {
                 if res[0] != Id(2) {
@@ -215,19 +231,15 @@ mod tests {
 
     async fn test_table_index_delete() {
         assert!(table_index_delete(
-            &"test123".to_string(),
+            "test123",
             vec![&String::from(""), &String::from("second")],
-            &vec![Id(2)]
+            &[Id(2)]
         )
         .await
         .is_ok());
 
-        match index_find(
-            &"test123".to_string(),
-            &String::from("second"),
-            &String(String::from("test_ins")),
-        )
-        .await
+        match index_find("test123", "second", Collation::Binary, &String(String::from("test_ins")))
+            .await
         {
             Ok(res) => {
                 if !res.is_empty() {
@@ -238,6 +250,28 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn collated_index() {
+        let names = [String("Alice".to_string()), String("ALICE".to_string())];
+        create_index("test_collated", "name", Collation::NoCase, &[Id(1), Id(2)], &names)
+            .await
+            .unwrap();
+        let attribute = Attribute {
+            collation: Collation::NoCase,
+            ..Attribute::new("name", AttributeType::String)
+        };
+        let attributes = [Attribute::new("", AttributeType::Id), attribute];
+        let alice = String("alice".to_string());
+        table_index_insert("test_collated", &attributes, vec![&Id(3), &alice]).await.unwrap();
+
+        // Strings equal under the collation share the key, like in comparisons of the column
+        let found = index_find("test_collated", "name", Collation::NoCase, &String("aLiCe".into()))
+            .await
+            .unwrap();
+        assert_eq!(found, vec![Id(1), Id(2), Id(3)]);
+        index_drop("test_collated").await.unwrap();
+    }
+
     async fn test_table_index_drop() {
         assert!(index_drop(&String::from("test123")).await.is_ok())
     }
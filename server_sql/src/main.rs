@@ -1,73 +1,230 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use crate::auth::DEFAULT_ADMIN_USER;
 use crate::stream_processor::StreamProcessor;
+use client_sql::{read_message, write_message};
 use dotenv::dotenv;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    io::{self, AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+    time::timeout,
 };
 
 pub mod allocator;
+pub mod auth;
 pub mod data;
 pub mod database;
 pub mod error;
 pub mod index;
 pub mod parser;
+pub mod session;
 pub mod stream_processor;
+pub mod tls;
+pub mod transaction;
+
+// Seconds in-flight requests get to finish after a shutdown signal
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+// Time a client gets to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How often row versions no snapshot needs anymore are cleaned up
+const GARBAGE_COLLECTION_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Serves clients until a shutdown signal, errors stop the server before it accepts anyone
+async fn run() -> io::Result<()> {
     dotenv().ok();
 
     //let url = std::env::var("BIND_URL").expect("BIND_URL must be set");
     let listener = TcpListener::bind("0.0.0.0:8000").await?;
     let stream_processor = Arc::new(stream_processor::StreamProcessor::default());
+    let loaded = stream_processor.load().await?;
+    println!("Loaded {}", loaded);
+    let admin = std::env::var("ADMIN_USER").unwrap_or_else(|_| DEFAULT_ADMIN_USER.to_string());
+    let admin_password = std::env::var("ADMIN_PASSWORD")
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ADMIN_PASSWORD must be set"))?;
+    stream_processor.add_superuser(&admin, &admin_password).await.map_err(io::Error::other)?;
+    let tls_acceptor =
+        tls::configured_acceptor(std::env::var("TLS_CERT").ok(), std::env::var("TLS_KEY").ok())?;
+    let shutdown_timeout = match std::env::var("SHUTDOWN_TIMEOUT") {
+        Ok(secs) => secs.parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "SHUTDOWN_TIMEOUT must be an integer")
+        })?,
+        Err(_) => DEFAULT_SHUTDOWN_TIMEOUT,
+    };
+    let collector = stream_processor.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GARBAGE_COLLECTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            collector.collect_garbage().await;
+        }
+    });
 
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        let (mut stream, _) = listener.accept().await?;
+        let (mut stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
         let sp_handle = stream_processor.clone();
-        tokio::spawn(async move {
-            process_stream(&mut stream, &sp_handle).await;
+        let tls_acceptor = tls_acceptor.clone();
+        let shutdown = shutdown_receiver.clone();
+        connections.spawn(async move {
+            match tls_acceptor {
+                Some(acceptor) => {
+                    // Clients failing or stalling the handshake are dropped
+                    let handshake = timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                    if let Ok(Ok(mut stream)) = handshake.await {
+                        process_stream(&mut stream, &sp_handle, shutdown).await;
+                    }
+                }
+                None => process_stream(&mut stream, &sp_handle, shutdown).await,
+            }
         });
+        // Forget connections which are already closed
+        while connections.try_join_next().is_some() {}
     }
+
+    drop(listener);
+    while connections.try_join_next().is_some() {}
+    println!("Shutting down, waiting for {} connections", connections.len());
+    shutdown_sender.send(true).ok();
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if timeout(Duration::from_secs(shutdown_timeout), drain).await.is_err() {
+        println!("Aborting {} connections still running", connections.len());
+        connections.shutdown().await;
+    }
+    let flushed = stream_processor.flush().await?;
+    println!("Flushed {} to {}", flushed, data::snapshot_path().display());
+    Ok(())
 }
 
-/// processes a stream and writes to socket after it has been processed
-pub async fn process_stream(stream: &mut TcpStream, processor: &Arc<StreamProcessor>) {
-    let mut buffer = vec![0; 1024];
-    loop {
-        let data_result = stream.read(&mut buffer).await;
+/// Completes on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
 
-        let data_length = match data_result {
-            Ok(0) => {
-                break;
-            }
-            Ok(size) => size,
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                continue;
+/// processes a stream and writes to socket after it has been processed,
+/// stops reading new messages once `shutdown` changes
+pub async fn process_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    processor: &Arc<StreamProcessor>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // The first message has to log in, the connection is closed otherwise
+    let message = tokio::select! {
+        message = read_request(stream) => message,
+        _ = shutdown.changed() => None,
+    };
+    let mut session = match message {
+        Some(data) => match processor.login(data).await {
+            Ok((session, response)) => {
+                if write_message(stream, &response).await.is_err() {
+                    return;
+                }
+                session
             }
-            Err(_) => {
-                break;
+            Err(e) => {
+                write_message(stream, &format!("{:?}", e)).await.ok();
+                return;
             }
+        },
+        None => return,
+    };
+    loop {
+        // Requests already being processed are finished, only waiting for new ones is cancelled
+        let data = tokio::select! {
+            message = read_request(stream) => match message {
+                Some(data) => data,
+                None => break,
+            },
+            _ = shutdown.changed() => break,
+        };
+        let response = match processor.process_str(&mut session, data).await {
+            Ok(s) => s,
+            Err(e) => format!("{:?}", e),
         };
+        if write_message(stream, &response).await.is_err() {
+            break;
+        }
+    }
+    processor.close_session(session).await;
+}
 
-        let data = String::from_utf8(buffer[0..data_length].to_vec()).unwrap();
-        match processor.process_str(data).await {
-            Ok(s) => {
-                stream.write(s.as_bytes()).await.unwrap();
-            }
-            Err(e) => {
-                let response = format!("{:?}", e);
-                stream.write(response.as_bytes()).await.unwrap();
-            }
+/// Reads the next request, None when the connection is closed. A request which cannot be
+/// read, e.g. one which is not UTF-8, is answered with the error and closes the connection
+async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Option<String> {
+    match read_message(stream).await {
+        Ok(message) => message,
+        Err(e) => {
+            write_message(stream, &format!("{:?}", e)).await.ok();
+            None
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use client_sql::{read_response, write_command_to_stream, Command, CommandType};
+    use tokio::io::AsyncWriteExt;
+
     #[test]
     fn ci_test() {
         assert_eq!(2, 1 + 1);
     }
+
+    #[tokio::test]
+    async fn framed_requests() {
+        let processor = Arc::new(StreamProcessor::default());
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut client, mut server) = io::duplex(256);
+        let (_shutdown_sender, shutdown) = watch::channel(false);
+        let connection =
+            tokio::spawn(async move { process_stream(&mut server, &processor, shutdown).await });
+        client_sql::login(&mut client, "admin".to_string(), "admin".to_string()).await.unwrap();
+
+        // Requests longer than any single read arrive whole
+        let values = vec!["(1)"; 2000].join(", ");
+        for (query, response) in [
+            ("create table t (id int primary key, a int)", "Nothing"),
+            (&format!("insert into t (a) values {}", values), ""),
+            ("select id from t where id = 1999", "Data([(attributes:[Id(1999)])])"),
+        ] {
+            let command = Command::create_command_from(query.to_string(), CommandType::Query);
+            write_command_to_stream(&mut client, command).await.unwrap();
+            let answer = read_response(&mut client).await.unwrap();
+            assert!(answer.starts_with(response), "{}", answer);
+        }
+
+        // A request which is not UTF-8 is refused without taking the server down
+        client.write_all(&[0, 0, 0, 2, 0xff, 0xfe]).await.unwrap();
+        let answer = read_response(&mut client).await.unwrap();
+        assert!(answer.contains("not valid UTF-8"), "{}", answer);
+        assert!(read_response(&mut client).await.is_err());
+        connection.await.unwrap();
+    }
 }
@@ -1,6 +1,27 @@
 macro_rules! value_pattern {
     () => {
-        unite!(r"\d+", "NULL", string_pattern!())
+        unite!(
+            number_pattern!(),
+            "NULL",
+            "TRUE",
+            "FALSE",
+            r"NOW\(\)",
+            blob_pattern!(),
+            string_pattern!()
+        )
+    };
+}
+
+macro_rules! number_pattern {
+    () => {
+        r"-?(?:\d+(?:\.\d*)?|\.\d+)(?:E[+-]?\d+)?"
+    };
+}
+
+// Hex digits are checked when the literal is parsed, for a clearer error
+macro_rules! blob_pattern {
+    () => {
+        r"X'\w*'"
     };
 }
 
@@ -24,13 +45,21 @@ macro_rules! string_or_ident_pattern {
 
 macro_rules! value_or_ident_pattern {
     () => {
-        unite!(r"\d+", "NULL", string_or_ident_pattern!())
+        unite!(number_pattern!(), "NULL", r"NOW\(\)", blob_pattern!(), string_or_ident_pattern!())
     };
 }
 
-macro_rules! value_or_ident_group {
+// Steps of a path into JSON following a column
+macro_rules! json_path_pattern {
     () => {
-        captured!(value_or_ident_pattern!())
+        r"(?:\s*->>?\s*(?:'[^']*'|[0-9]+))*"
+    };
+}
+
+// Compared value or column, a column may be followed by a JSON path
+macro_rules! operand_group {
+    () => {
+        captured!(value_or_ident_pattern!(), json_path_pattern!())
     };
 }
 
@@ -49,13 +78,25 @@ macro_rules! unite {
   };
 }
 
+// A lone column is a boolean predicate
 macro_rules! where_pattern {
     () => {
         concat!(
             r"(WHERE)\s+",
-            value_or_ident_group!(),
-            r"\s*(=|<|>|>=|<=|!=|<>)\s*",
-            value_or_ident_group!()
+            unite!(
+                concat!(
+                    operand_group!(),
+                    unite!(
+                        concat!(
+                            r"\s*(=|<|>|>=|<=|!=|<>)\s*",
+                            operand_group!(),
+                            optional!(s1!(), "(COLLATE)", s1!(), captured!(collation_pattern!()))
+                        ),
+                        r"\s+(IS)\s+(?:(NOT)\s+)?(NULL)"
+                    )
+                ),
+                captured!(string_or_ident_pattern!())
+            )
         )
     };
 }
@@ -66,6 +107,13 @@ macro_rules! commas {
     };
 }
 
+// Captures a whole comma separated list, items must not contain capture groups
+macro_rules! list {
+    ($x:expr) => {
+        captured!(commas!($x))
+    };
+}
+
 macro_rules! from_where_pattern {
     () => {
         concat!(
@@ -77,11 +125,29 @@ macro_rules! from_where_pattern {
     };
 }
 
+macro_rules! constraint_pattern {
+    () => {
+        unite!(
+            concat!("PRIMARY", s1!(), "KEY"),
+            concat!("NOT", s1!(), "NULL"),
+            "NULL",
+            concat!("DEFAULT", s1!(), value_pattern!()),
+            concat!("COLLATE", s1!(), collation_pattern!())
+        )
+    };
+}
+
+macro_rules! collation_pattern {
+    () => {
+        unite!("BINARY", "NOCASE", "UNICODE")
+    };
+}
+
 macro_rules! attr_pattern {
     () => {
         s_delimited!(
-            captured!(string_or_ident_pattern!()),
-            captured!(type_pattern!(), optional!(s1!(), "PRIMARY", s1!(), "KEY"))
+            string_or_ident_pattern!(),
+            concat!(type_pattern!(), "(?:", s1!(), constraint_pattern!(), ")*")
         )
     };
 }
@@ -130,13 +196,29 @@ macro_rules! s1 {
 
 macro_rules! type_pattern {
     () => {
-        "(?:INT|INTEGER|STRING|TEXT|VARCHAR|DATA|BLOB)"
+        unite!(
+            "INT|INTEGER|STRING|TEXT|VARCHAR|DATA|BLOB|REAL|DOUBLE|FLOAT|BOOLEAN|BOOL",
+            "DATE|TIMESTAMP|TIME|JSON",
+            concat!(
+                "(?:DECIMAL|NUMERIC)",
+                optional!(
+                    s!(),
+                    r"\(",
+                    s!(),
+                    r"\d+",
+                    optional!(s!(), ",", s!(), r"\d+"),
+                    s!(),
+                    r"\)"
+                )
+            )
+        )
     };
 }
 
 pub(crate) use {
-    anchored, attr_pattern, captured, command, commas, from_where_pattern, intersperse,
-    maybe_s_delimited, optional, s, s1, s_delimited, string_or_ident_pattern, string_pattern,
-    type_pattern, unite, value_or_ident_group, value_or_ident_pattern, value_pattern,
-    where_pattern,
+    anchored, attr_pattern, blob_pattern, captured, collation_pattern, command, commas,
+    constraint_pattern, from_where_pattern, intersperse, json_path_pattern, list,
+    maybe_s_delimited, number_pattern, operand_group, optional, s, s1, s_delimited,
+    string_or_ident_pattern, string_pattern, type_pattern, unite, value_or_ident_pattern,
+    value_pattern, where_pattern,
 };
@@ -5,7 +5,11 @@ mod utils;
 use self::patterns::capture_command;
 use self::utils::*;
 use crate::{
-    database::{Attribute, Comparison, DataAttributes, DatabaseTable},
+    auth::Privilege,
+    database::{
+        AlterTable, Assignments, Attribute, Comparison, DataAttributes, DatabaseTable, Extracted,
+        OnConflict, OrderBy,
+    },
     error::UserError,
 };
 use std::{collections::HashMap, sync::Arc};
@@ -14,12 +18,90 @@ use tokio::sync::RwLock;
 #[non_exhaustive]
 #[derive(PartialEq)]
 pub enum Command {
-    Create { name: String, attributes: Vec<Attribute> },
-    Insert { table_name: String, data: DataAttributes },
-    Delete { table_name: String, attr_pos: usize, comparison: Comparison },
-    Select { table_name: String, attr_pos: usize, comparison: Comparison, selected: Vec<usize> },
-    Drop { name: String },
-    CreateIndex { table_name: String, attr_positions: Vec<usize> },
+    Create {
+        name: String,
+        attributes: Vec<Attribute>,
+    },
+    // Columns listed by RETURNING are answered instead of the ids or the number of rows
+    Insert {
+        table_name: String,
+        rows: Vec<DataAttributes>,
+        on_conflict: OnConflict,
+        returning: Option<Vec<usize>>,
+    },
+    Update {
+        table_name: String,
+        set: Assignments,
+        attr_pos: usize,
+        comparison: Comparison,
+        returning: Option<Vec<usize>>,
+    },
+    Delete {
+        table_name: String,
+        attr_pos: usize,
+        comparison: Comparison,
+        returning: Option<Vec<usize>>,
+    },
+    Select {
+        table_name: String,
+        attr_pos: usize,
+        comparison: Comparison,
+        selected: Vec<usize>,
+        extracted: Extracted,
+        order_by: Option<OrderBy>,
+    },
+    Drop {
+        name: String,
+    },
+    AlterTable {
+        table_name: String,
+        alter: AlterTable,
+    },
+    CreateIndex {
+        table_name: String,
+        attr_positions: Vec<usize>,
+    },
+    CreateUser {
+        name: String,
+        password: String,
+    },
+    AlterUser {
+        name: String,
+        password: String,
+    },
+    DropUser {
+        name: String,
+    },
+    // Grants without a table name apply to all tables
+    Grant {
+        privileges: Vec<Privilege>,
+        table_name: Option<String>,
+        user: String,
+    },
+    Revoke {
+        privileges: Vec<Privilege>,
+        table_name: Option<String>,
+        user: String,
+    },
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint {
+        name: String,
+    },
+    RollbackTo {
+        name: String,
+    },
+    Release {
+        name: String,
+    },
+}
+
+impl Command {
+    /// Creates, changes or removes a table definition
+    pub fn changes_schema(&self) -> bool {
+        matches!(self, Command::Create { .. } | Command::Drop { .. } | Command::AlterTable { .. })
+    }
 }
 
 const QUOTES: &[char] = &['\'', '"'];
@@ -28,56 +110,146 @@ pub async fn get_command(
     input: &str,
     tables: Arc<RwLock<HashMap<String, DatabaseTable>>>,
 ) -> Result<Command, UserError> {
-    let lowercased = capture_command(input)
-        .into_iter()
-        .map(|s| if s.starts_with(QUOTES) { s.to_owned() } else { s.to_ascii_lowercase() })
-        .collect::<Vec<_>>();
+    let lowercased = capture_command(input).into_iter().map(lowercase_unquoted).collect::<Vec<_>>();
 
-    let command = match lowercased.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
-        ["create", "table", table, attrs @ ..] => {
-            Command::Create { name: table.to_string(), attributes: parse_attributes(attrs)? }
+    let tokens = lowercased.iter().map(String::as_str).collect::<Vec<_>>();
+    // Only statements changing rows end with RETURNING
+    let (tokens, returning) = match tokens.as_slice() {
+        [statement @ .., "returning", cols]
+            if matches!(statement.first(), Some(&("insert" | "update" | "delete"))) =>
+        {
+            (statement, Some(*cols))
         }
-        ["create", "index", _index, "on", _table, _cols @ ..] => {
+        tokens => (tokens, None),
+    };
+    // Only SELECT ends with ORDER BY
+    let (tokens, order_by) = match tokens {
+        [statement @ .., "order", col, direction @ ("asc" | "desc")]
+            if statement.first() == Some(&"select") =>
+        {
+            (statement, Some((*col, *direction)))
+        }
+        [statement @ .., "order", col] if statement.first() == Some(&"select") => {
+            (statement, Some((*col, "asc")))
+        }
+        tokens => (tokens, None),
+    };
+
+    let command = match tokens {
+        ["create", "table", table, attrs] => Command::Create {
+            name: table.to_string(),
+            attributes: parse_attributes(&split_list(attrs))?,
+        },
+        ["create", "index", _index, "on", _table, _cols] => {
             return Err(UserError::Other("Named indices not supported yet!"))
         }
-        ["create", "index", "on", table, cols @ ..] => Command::CreateIndex {
+        ["create", "index", "on", table, cols] => Command::CreateIndex {
             table_name: table.to_string(),
             attr_positions: parse_cols(
-                cols,
+                &split_list(cols),
                 tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
             )?,
         },
-        ["select", cols @ .., "from", table] => make_select_command(
+        ["select", cols, "from", table] => make_select_command(
             table,
             tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
             cols,
             None,
+            order_by,
         )?,
-        ["select", cols @ .., "from", table, "where", a, cmp, b] => make_select_command(
+        ["select", cols, "from", table, "where", condition @ ..] => make_select_command(
             table,
             tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
             cols,
-            Some((a, cmp, b)),
+            Some(condition),
+            order_by,
+        )?,
+        ["insert", "into", table, "values", rows, on_conflict @ ..] => make_insert_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            None,
+            rows,
+            on_conflict,
+            returning,
         )?,
-        ["insert", "into", table, "values", values @ ..] => Command::Insert {
+        ["insert", "into", table, cols, "values", rows, on_conflict @ ..] => make_insert_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            Some(cols),
+            rows,
+            on_conflict,
+            returning,
+        )?,
+        ["update", table, "set", assignments] => make_update_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            assignments,
+            None,
+            returning,
+        )?,
+        ["update", table, "set", assignments, "where", condition @ ..] => make_update_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            assignments,
+            Some(condition),
+            returning,
+        )?,
+        ["delete", "from", table] => make_delete_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            None,
+            returning,
+        )?,
+        ["delete", "from", table, "where", condition @ ..] => make_delete_command(
+            table,
+            tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
+            Some(condition),
+            returning,
+        )?,
+        ["drop", "table", table] => Command::Drop { name: table.to_string() },
+        ["alter", "table", table, "add", attr] => {
+            Command::AlterTable { table_name: table.to_string(), alter: parse_added_column(attr)? }
+        }
+        ["alter", "table", table, "drop", name] => Command::AlterTable {
             table_name: table.to_string(),
-            data: DataAttributes {
-                attributes: parse_values(
-                    values,
-                    tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
-                )?,
+            alter: AlterTable::DropColumn { name: name.to_string() },
+        },
+        ["alter", "table", table, "rename", "to", new_name] => Command::AlterTable {
+            table_name: table.to_string(),
+            alter: AlterTable::Rename { new_name: new_name.to_string() },
+        },
+        ["alter", "table", table, "rename", name, "to", new_name] => Command::AlterTable {
+            table_name: table.to_string(),
+            alter: AlterTable::RenameColumn {
+                name: name.to_string(),
+                new_name: new_name.to_string(),
             },
         },
-        ["delete", "from", table, "where", a, cmp, b] => {
-            let (attr_pos, comparison) = parse_comparison(
-                a,
-                cmp,
-                b,
-                tables.read().await.get(*table).ok_or(UserError::Other("No such table"))?,
-            )?;
-            Command::Delete { table_name: table.to_string(), attr_pos, comparison }
-        }
-        ["drop", "table", table] => Command::Drop { name: table.to_string() },
+        ["create", "user", name, "password", password] => Command::CreateUser {
+            name: name.to_string(),
+            password: parse_string(password)?.to_owned(),
+        },
+        ["alter", "user", name, "password", password] => Command::AlterUser {
+            name: name.to_string(),
+            password: parse_string(password)?.to_owned(),
+        },
+        ["drop", "user", name] => Command::DropUser { name: name.to_string() },
+        ["grant", privileges, "on", table, "to", user] => Command::Grant {
+            privileges: parse_privileges(&split_list(privileges))?,
+            table_name: parse_grant_target(table),
+            user: user.to_string(),
+        },
+        ["revoke", privileges, "on", table, "from", user] => Command::Revoke {
+            privileges: parse_privileges(&split_list(privileges))?,
+            table_name: parse_grant_target(table),
+            user: user.to_string(),
+        },
+        ["begin"] | ["start"] => Command::Begin,
+        ["commit"] => Command::Commit,
+        ["rollback"] => Command::Rollback,
+        ["savepoint", name] => Command::Savepoint { name: name.to_string() },
+        ["rollback", "to", name] => Command::RollbackTo { name: name.to_string() },
+        ["release", name] => Command::Release { name: name.to_string() },
         [] => return Err(UserError::SyntaxError),
         _ => unreachable!(),
     };
@@ -87,23 +259,112 @@ pub async fn get_command(
 fn make_select_command(
     table_name: &str,
     table: &DatabaseTable,
-    cols: &[&str],
-    where_clause: Option<(&str, &str, &str)>,
+    cols: &str,
+    where_clause: Option<&[&str]>,
+    order_by: Option<(&str, &str)>,
 ) -> Result<Command, UserError> {
     let table_name = table_name.to_owned();
-    let selected = parse_cols(cols, table)?;
-    if let Some((lhs, cmp, rhs)) = where_clause {
-        let (attr_pos, comparison) = parse_comparison(lhs, cmp, rhs, table)?;
-        Ok(Command::Select { table_name, selected, comparison, attr_pos })
-    } else {
-        Ok(Command::Select { table_name, selected, comparison: Comparison::All, attr_pos: 0 })
+    let (selected, extracted) = parse_selected(&split_list(cols), table)?;
+    let (attr_pos, comparison) = parse_where(where_clause, table)?;
+    let order_by = order_by
+        .map(|(col, direction)| {
+            let attr_pos = parse_cols(&[col], table)?[0];
+            Ok::<_, UserError>(OrderBy { attr_pos, descending: direction == "desc" })
+        })
+        .transpose()?;
+    Ok(Command::Select { table_name, selected, extracted, comparison, attr_pos, order_by })
+}
+
+fn parse_where(
+    where_clause: Option<&[&str]>,
+    table: &DatabaseTable,
+) -> Result<(usize, Comparison), UserError> {
+    match where_clause {
+        Some([col, "is", "null"]) => parse_null_check(col, false, table),
+        Some([col, "is", "not", "null"]) => parse_null_check(col, true, table),
+        Some([lhs, cmp, rhs]) => parse_comparison(lhs, cmp, rhs, None, table),
+        Some([lhs, cmp, rhs, "collate", collation]) => {
+            parse_comparison(lhs, cmp, rhs, Some(collation), table)
+        }
+        Some([col]) => parse_predicate_column(col, table),
+        Some(_) => unreachable!(),
+        None => Ok((0, Comparison::All)),
     }
 }
 
+fn parse_returning(
+    returning: Option<&str>,
+    table: &DatabaseTable,
+) -> Result<Option<Vec<usize>>, UserError> {
+    returning.map(|cols| parse_cols(&split_list(cols), table)).transpose()
+}
+
+fn make_insert_command(
+    table_name: &str,
+    table: &DatabaseTable,
+    cols: Option<&str>,
+    rows: &str,
+    on_conflict: &[&str],
+    returning: Option<&str>,
+) -> Result<Command, UserError> {
+    Ok(Command::Insert {
+        table_name: table_name.to_owned(),
+        rows: parse_rows(rows, cols, table)?,
+        on_conflict: parse_on_conflict(on_conflict, table)?,
+        returning: parse_returning(returning, table)?,
+    })
+}
+
+fn make_update_command(
+    table_name: &str,
+    table: &DatabaseTable,
+    assignments: &str,
+    where_clause: Option<&[&str]>,
+    returning: Option<&str>,
+) -> Result<Command, UserError> {
+    let (attr_pos, comparison) = parse_where(where_clause, table)?;
+    Ok(Command::Update {
+        table_name: table_name.to_owned(),
+        set: parse_assignments(&split_list(assignments), table)?,
+        attr_pos,
+        comparison,
+        returning: parse_returning(returning, table)?,
+    })
+}
+
+fn make_delete_command(
+    table_name: &str,
+    table: &DatabaseTable,
+    where_clause: Option<&[&str]>,
+    returning: Option<&str>,
+) -> Result<Command, UserError> {
+    let (attr_pos, comparison) = parse_where(where_clause, table)?;
+    Ok(Command::Delete {
+        table_name: table_name.to_owned(),
+        attr_pos,
+        comparison,
+        returning: parse_returning(returning, table)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::{AttributeType, Database};
+    use crate::database::{
+        AttributeType, Collation, DataAttribute, Database, DatePart, Extraction, Float, JsonPath,
+        JsonStep, SetValue,
+    };
+    use chrono::{NaiveDate, NaiveTime};
+
+    /// Creates the table the statement describes, answering its parsed columns
+    async fn create_table(db: &Database, create: &str) -> Vec<Attribute> {
+        let Ok(Command::Create { name, attributes }) = get_command(create, db.tables.clone()).await
+        else {
+            panic!()
+        };
+        db.create_table(&name, attributes.clone(), "admin").await.unwrap();
+        attributes
+    }
 
     #[tokio::test]
     async fn parse_command() -> Result<(), UserError> {
@@ -112,11 +373,587 @@ mod tests {
         let expected = Command::Create {
             name: "x".to_string(),
             attributes: vec![
-                Attribute { name: "a".to_string(), attribute_type: AttributeType::Number },
-                Attribute { name: "b".to_string(), attribute_type: AttributeType::Id },
+                Attribute::new("a", AttributeType::Number),
+                Attribute::new("b", AttributeType::Id),
             ],
         };
         assert!(command == expected);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn parse_user_commands() -> Result<(), UserError> {
+        let db = Database::default();
+        let command =
+            get_command("CREATE USER Alice WITH PASSWORD 'S3cret';", db.tables.clone()).await?;
+        let expected =
+            Command::CreateUser { name: "alice".to_string(), password: "S3cret".to_string() };
+        assert!(command == expected);
+        let command = get_command("alter user alice password 'x'", db.tables.clone()).await?;
+        let expected = Command::AlterUser { name: "alice".to_string(), password: "x".to_string() };
+        assert!(command == expected);
+        let command = get_command("drop user alice", db.tables).await?;
+        assert!(command == Command::DropUser { name: "alice".to_string() });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_grants() -> Result<(), UserError> {
+        let db = Database::default();
+        let command =
+            get_command("GRANT SELECT, INSERT, DROP ON people TO alice", db.tables.clone()).await?;
+        let expected = Command::Grant {
+            privileges: vec![Privilege::Select, Privilege::Insert, Privilege::Drop],
+            table_name: Some("people".to_string()),
+            user: "alice".to_string(),
+        };
+        assert!(command == expected);
+        let command = get_command("revoke all on * from alice", db.tables).await?;
+        let expected = Command::Revoke {
+            privileges: Privilege::ALL.to_vec(),
+            table_name: None,
+            user: "alice".to_string(),
+        };
+        assert!(command == expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_alter_table() -> Result<(), UserError> {
+        let db = Database::default();
+        let command =
+            get_command("ALTER TABLE t ADD COLUMN Note text DEFAULT 'None'", db.tables.clone())
+                .await?;
+        let attribute = Attribute {
+            default: Some(DataAttribute::String("None".to_string())),
+            ..Attribute::new("note", AttributeType::String)
+        };
+        let alter = AlterTable::AddColumn { attribute };
+        assert!(command == Command::AlterTable { table_name: "t".to_string(), alter });
+        let added = get_command("alter table t add id int primary key", db.tables.clone()).await;
+        assert!(added.is_err());
+        let added = get_command("alter table t add age int default 'x'", db.tables).await;
+        assert!(added.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_constraints() -> Result<(), UserError> {
+        let db = Database::default();
+        let create =
+            "create table t (id int primary key, a text not null default 'x y', b int null)";
+        let attributes = create_table(&db, create).await;
+        let a = Attribute {
+            not_null: true,
+            default: Some(DataAttribute::String("x y".to_string())),
+            ..Attribute::new("a", AttributeType::String)
+        };
+        let expected = vec![
+            Attribute::new("id", AttributeType::Id),
+            a,
+            Attribute::new("b", AttributeType::Number),
+        ];
+        assert!(attributes == expected);
+        let pk_default = "create table u (id int primary key default 1)";
+        assert!(get_command(pk_default, db.tables.clone()).await.is_err());
+
+        // Missing values are filled with the defaults instead of leaving the row short
+        let command = get_command("insert into t values ('z')", db.tables).await?;
+        let Command::Insert { rows, .. } = command else { panic!() };
+        let expected =
+            [DataAttribute::NoneId, DataAttribute::String("z".to_string()), DataAttribute::None];
+        assert_eq!(rows[0].attributes, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_insert_rows() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (a text, id int primary key, b int default 7, c int)";
+        create_table(&db, create).await;
+        let insert = "insert into t (c, a) values (1, 'x'), (2, null)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let row = |a, c| DataAttributes {
+            attributes: vec![a, DataAttribute::NoneId, DataAttribute::Number(7), c],
+        };
+        let x = DataAttribute::String("x".to_string());
+        let expected = vec![
+            row(x, DataAttribute::Number(1)),
+            row(DataAttribute::None, DataAttribute::Number(2)),
+        ];
+        assert_eq!(rows, expected);
+        let insert = "insert into t (id, c) values (5, 1)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        assert_eq!(rows[0].attributes[1], DataAttribute::Id(5));
+        for insert in [
+            "insert into t (c, a) values (1)",
+            "insert into t (c) values (1, 2)",
+            "insert into t (c, c) values (1, 2)",
+            "insert into t (id) values ('x')",
+            "insert into t (d) values (1)",
+        ] {
+            assert!(get_command(insert, db.tables.clone()).await.is_err(), "{}", insert);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_update_and_returning() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, a int, b text)";
+        create_table(&db, create).await;
+        let update = "update t set b = 'x', a = NULL where a > 3 returning *";
+        let expected = Command::Update {
+            table_name: "t".to_string(),
+            set: vec![
+                (2, SetValue::Value(DataAttribute::String("x".to_string()))),
+                (1, SetValue::Value(DataAttribute::None)),
+            ],
+            attr_pos: 1,
+            comparison: Comparison::Higher(DataAttribute::Number(3)),
+            returning: Some(vec![0, 1, 2]),
+        };
+        assert!(get_command(update, db.tables.clone()).await? == expected);
+        let delete = "delete from t returning b, id";
+        let expected = Command::Delete {
+            table_name: "t".to_string(),
+            attr_pos: 0,
+            comparison: Comparison::All,
+            returning: Some(vec![2, 0]),
+        };
+        assert!(get_command(delete, db.tables.clone()).await? == expected);
+        let insert = "insert into t values (1, 'y') returning id";
+        let Command::Insert { returning, .. } = get_command(insert, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert_eq!(returning, Some(vec![0]));
+        for statement in [
+            "update t set id = 2",
+            "update t set c = 2",
+            "delete from t returning c",
+            "select * from t returning id",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_blobs() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, b blob default X'00')";
+        let attributes = create_table(&db, create).await;
+        assert_eq!(attributes[1].default, Some(DataAttribute::Data(vec![0])));
+        let insert = "insert into t (b) values (X'DEADbeef'), (x''), (NULL)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let blobs = rows.into_iter().map(|row| row.attributes[1].clone()).collect::<Vec<_>>();
+        let expected = [
+            DataAttribute::Data(vec![0xde, 0xad, 0xbe, 0xef]),
+            DataAttribute::Data(vec![]),
+            DataAttribute::None,
+        ];
+        assert_eq!(blobs, expected);
+        let select = "select id from t where X'0A' < b";
+        let Command::Select { comparison, .. } = get_command(select, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert!(comparison == Comparison::Higher(DataAttribute::Data(vec![10])));
+        for statement in [
+            "insert into t (b) values (X'abc')",
+            "insert into t (b) values (X'xy')",
+            "insert into t (b) values ('text')",
+            "select * from t where b = X'0'",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_reals() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, r real default 1.5, d double, f float)";
+        let attributes = create_table(&db, create).await;
+        assert_eq!(attributes[1].default, Some(DataAttribute::Real(Float(1.5))));
+        assert!(attributes[1..].iter().all(|attr| attr.attribute_type == AttributeType::Real));
+        let insert = "insert into t (r) values (-2.5e2), (3), ('NaN'), ('-INFINITY'), (NULL)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let reals = rows.into_iter().map(|row| row.attributes[1].clone()).collect::<Vec<_>>();
+        let expected = [
+            DataAttribute::Real(Float(-250.0)),
+            DataAttribute::Real(Float(3.0)),
+            DataAttribute::Real(Float(f64::NAN)),
+            DataAttribute::Real(Float(f64::NEG_INFINITY)),
+            DataAttribute::None,
+        ];
+        assert_eq!(reals, expected);
+        let select = "select id from t where .5 <= r";
+        let Command::Select { comparison, .. } = get_command(select, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert!(comparison == Comparison::HigherOrEqual(DataAttribute::Real(Float(0.5))));
+        for statement in [
+            "insert into t (r) values ('text')",
+            "insert into t (r) values (X'00')",
+            "insert into t (id) values (1.5)",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_booleans() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, active boolean default true, b bool)";
+        let attributes = create_table(&db, create).await;
+        assert_eq!(attributes[1].default, Some(DataAttribute::Bool(true)));
+        assert!(attributes[2].attribute_type == AttributeType::Bool);
+        let insert = "insert into t (active, b) values (FALSE, True), (NULL, false)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let expected = [
+            [DataAttribute::Bool(false), DataAttribute::Bool(true)],
+            [DataAttribute::None, DataAttribute::Bool(false)],
+        ];
+        assert!(rows.iter().map(|row| &row.attributes[1..]).eq(expected.iter()));
+        for (statement, (attr_pos, comparison)) in [
+            ("select id from t where b", (2, Comparison::Equal(DataAttribute::Bool(true)))),
+            (
+                "update t set b = true where \"active\"",
+                (1, Comparison::Equal(DataAttribute::Bool(true))),
+            ),
+            (
+                "delete from t where false <> active",
+                (1, Comparison::NotEqual(DataAttribute::Bool(false))),
+            ),
+        ] {
+            let (Command::Select { attr_pos: pos, comparison: cmp, .. }
+            | Command::Update { attr_pos: pos, comparison: cmp, .. }
+            | Command::Delete { attr_pos: pos, comparison: cmp, .. }) =
+                get_command(statement, db.tables.clone()).await?
+            else {
+                panic!()
+            };
+            assert!(pos == attr_pos && cmp == comparison, "{}", statement);
+        }
+        for statement in [
+            "insert into t (b) values (1)",
+            "insert into t (b) values ('true')",
+            "select * from t where id",
+            "select * from t where missing",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_dates() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, d date, t time, ts timestamp)";
+        create_table(&db, create).await;
+        let insert = "insert into t (d, t, ts) values ('2024-02-29', '13:45', '2024-02-29T13:45:30+02:00'), \
+                      (NULL, '08:00:00.5', '2024-03-01 00:00')";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        let expected = [
+            DataAttribute::Date(date),
+            DataAttribute::Time(NaiveTime::from_hms_opt(13, 45, 0).unwrap()),
+            DataAttribute::Timestamp(date.and_hms_opt(11, 45, 30).unwrap()),
+            DataAttribute::None,
+            DataAttribute::Time(NaiveTime::from_hms_milli_opt(8, 0, 0, 500).unwrap()),
+            DataAttribute::Timestamp(date.succ_opt().unwrap().and_hms_opt(0, 0, 0).unwrap()),
+        ];
+        assert!(rows.iter().flat_map(|row| &row.attributes[1..]).eq(expected.iter()));
+
+        let select = "select extract(year from d), ts, EXTRACT(Hour FROM \"ts\") from t \
+                      where ts >= '2024-01-01'";
+        let Command::Select { selected, extracted, comparison, .. } =
+            get_command(select, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert_eq!(selected, [1, 3, 3]);
+        let expected =
+            [(0, Extraction::Part(DatePart::Year)), (2, Extraction::Part(DatePart::Hour))];
+        assert_eq!(extracted, expected);
+        let midnight = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0);
+        assert!(
+            comparison == Comparison::HigherOrEqual(DataAttribute::Timestamp(midnight.unwrap()))
+        );
+        for statement in [
+            "insert into t (d) values ('2023-02-29')",
+            "insert into t (t) values ('25:00')",
+            "insert into t (ts) values (1700000000)",
+            "insert into t (id) values (now())",
+            "select extract(hour from d) from t",
+            "select extract(year from id) from t",
+            "select extract(week from ts) from t",
+            "select * from t where d = 'yesterday'",
+            "create table u (ts timestamp default now())",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_decimals() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, price decimal(5, 2) default 0, \
+                      n numeric (3), d decimal)";
+        let attributes = create_table(&db, create).await;
+        let types = attributes.iter().map(|attr| attr.attribute_type.clone()).collect::<Vec<_>>();
+        let expected = [
+            AttributeType::Id,
+            AttributeType::Decimal { precision: 5, scale: 2 },
+            AttributeType::Decimal { precision: 3, scale: 0 },
+            AttributeType::Decimal { precision: 18, scale: 0 },
+        ];
+        assert!(types == expected);
+        assert_eq!(attributes[1].default, Some(DataAttribute::Decimal("0.00".parse().unwrap())));
+
+        let insert = "insert into t (price, n) values (123.456, -999), (.005, 1.5e2)";
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        let decimals = rows.iter().flat_map(|row| &row.attributes[1..3]).map(|attr| match attr {
+            DataAttribute::Decimal(decimal) => decimal.to_string(),
+            _ => panic!(),
+        });
+        assert!(decimals.eq(["123.46", "-999", "0.01", "150"]));
+        let select = "select id from t where price > 1.005";
+        let Command::Select { comparison, .. } = get_command(select, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert!(comparison == Comparison::Higher(DataAttribute::Decimal("1.005".parse().unwrap())));
+        for statement in [
+            "insert into t (price) values (1000)",
+            "insert into t (price) values (999.995)",
+            "insert into t (n) values ('1')",
+            "create table u (a decimal(0))",
+            "create table u (a decimal(2, 3))",
+            "create table u (a decimal(19, 2))",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_json() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, doc json, a int)";
+        let attributes = create_table(&db, create).await;
+        assert!(attributes[1].attribute_type == AttributeType::Json);
+
+        let insert = r#"insert into t (doc) values ('{ "a": [1, 2] }')"#;
+        let Command::Insert { rows, .. } = get_command(insert, db.tables.clone()).await? else {
+            panic!()
+        };
+        assert_eq!(rows[0].attributes[1], DataAttribute::Json(r#"{"a":[1,2]}"#.to_string()));
+        let select = "select doc->'a'->>1 from t where doc ->> 'b' = 'x'";
+        let Command::Select { extracted, comparison, .. } =
+            get_command(select, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        let path = |steps, unwrap| JsonPath { steps, unwrap };
+        let expected = path(vec![JsonStep::Key("a".to_string()), JsonStep::Index(1)], true);
+        assert!(extracted == [(0, Extraction::Path(expected))]);
+        let expected = Comparison::Path(
+            path(vec![JsonStep::Key("b".to_string())], true),
+            Box::new(Comparison::Equal(DataAttribute::String("x".to_string()))),
+        );
+        assert!(comparison == expected);
+        for statement in [
+            "insert into t (doc) values ('{')",
+            "select a->'b' from t",
+            "select doc->>'a'->'b' from t",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_null_checks() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, a int, doc json)";
+        create_table(&db, create).await;
+        for (select, expected) in [
+            ("select id from t where a is null", (1, Comparison::IsNull)),
+            ("select id from t where \"a\" Is Not Null", (1, Comparison::IsNotNull)),
+            ("select id from t where a = null", (1, Comparison::Equal(DataAttribute::None))),
+            (
+                "select id from t where doc->>'b' is null",
+                (
+                    2,
+                    Comparison::Path(
+                        JsonPath { steps: vec![JsonStep::Key("b".to_string())], unwrap: true },
+                        Box::new(Comparison::IsNull),
+                    ),
+                ),
+            ),
+        ] {
+            let Command::Select { attr_pos, comparison, .. } =
+                get_command(select, db.tables.clone()).await?
+            else {
+                panic!()
+            };
+            assert!((attr_pos, comparison) == expected, "{}", select);
+        }
+        for statement in ["select id from t where 1 is null", "select id from t where b is null"] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_comparison_types() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, name text, n int, r real, \
+                      d decimal(5, 2), b bool, at timestamp, x blob)";
+        create_table(&db, create).await;
+        for statement in [
+            "select id from t where id = 1",
+            "select id from t where n > 1.5",
+            "select id from t where r <= 2",
+            "select id from t where d = 3",
+            "select id from t where at < now()",
+            "select id from t where name = null",
+            "delete from t where x = x'00'",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_ok(), "{}", statement);
+        }
+        for statement in [
+            "select id from t where name > 5",
+            "select id from t where n = 'a'",
+            "select id from t where id = 'a'",
+            "select id from t where id = 1.5",
+            "select id from t where b = 1",
+            "select id from t where x = 'a'",
+            "update t set n = 1 where r = true",
+            "delete from t where name = x'00'",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_collations() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, a text collate nocase, b text, \
+                      c text COLLATE Unicode not null)";
+        let attributes = create_table(&db, create).await;
+        let collations = attributes.iter().map(|attr| attr.collation).collect::<Vec<_>>();
+        let expected =
+            [Collation::Binary, Collation::NoCase, Collation::Binary, Collation::Unicode];
+        assert_eq!(collations, expected);
+
+        let text = |text: &str| DataAttribute::String(text.to_string());
+        let collated = |collation, cmp| Comparison::Collated(collation, Box::new(cmp));
+        for (select, expected) in [
+            (
+                "select id from t where a = 'Ab'",
+                collated(Collation::NoCase, Comparison::Equal(text("ab"))),
+            ),
+            ("select id from t where a = 'Ab' collate binary", Comparison::Equal(text("Ab"))),
+            (
+                "select id from t where 'Ab' < b collate nocase",
+                collated(Collation::NoCase, Comparison::Higher(text("ab"))),
+            ),
+            (
+                "select id from t where c != 'ÄB'",
+                collated(Collation::Unicode, Comparison::NotEqual(text("äb"))),
+            ),
+        ] {
+            let Command::Select { comparison, .. } = get_command(select, db.tables.clone()).await?
+            else {
+                panic!()
+            };
+            assert!(comparison == expected, "{}", select);
+        }
+        for (select, attr_pos, descending) in [
+            ("select id from t order by a", 1, false),
+            ("select * from t where b = 'x' order by c DESC", 3, true),
+            ("select b from t order by id asc", 0, false),
+        ] {
+            let Command::Select { order_by, .. } = get_command(select, db.tables.clone()).await?
+            else {
+                panic!()
+            };
+            assert_eq!(order_by, Some(OrderBy { attr_pos, descending }), "{}", select);
+        }
+        for statement in [
+            "create table u (a int collate nocase)",
+            "create table u (a text collate latin1)",
+            "select id from t where id = 1 collate nocase",
+            "select id from t order by d",
+        ] {
+            assert!(get_command(statement, db.tables.clone()).await.is_err(), "{}", statement);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_on_conflict() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (id int primary key, a int, b text)";
+        create_table(&db, create).await;
+        let upsert = "insert into t (id, a) values (1, 2) on conflict (id) do update set a = excluded.a, b = 'x'";
+        let Command::Insert { on_conflict, .. } = get_command(upsert, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        let set = vec![
+            (1, SetValue::Excluded(1)),
+            (2, SetValue::Value(DataAttribute::String("x".to_string()))),
+        ];
+        assert!(on_conflict == OnConflict::Update(set));
+        let ignore = "insert into t values (2, 'y') on conflict do nothing";
+        let Command::Insert { on_conflict, .. } = get_command(ignore, db.tables.clone()).await?
+        else {
+            panic!()
+        };
+        assert!(on_conflict == OnConflict::Nothing);
+        for upsert in [
+            "insert into t values (2, 'y') on conflict (a) do nothing",
+            "insert into t values (2, 'y') on conflict do update set id = 3",
+            "insert into t values (2, 'y') on conflict do update set a = excluded.b",
+            "insert into t values (2, 'y') on conflict do update set a = 'x'",
+        ] {
+            assert!(get_command(upsert, db.tables.clone()).await.is_err(), "{}", upsert);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_many_columns() -> Result<(), UserError> {
+        let db = Database::default();
+        let create = "create table t (a int primary key, b int, c text, d blob)";
+        let attributes = create_table(&db, create).await;
+        let names = attributes.iter().map(|attr| attr.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        let command = get_command("select d, c, b, a from t", db.tables).await?;
+        assert!(matches!(command, Command::Select { selected, .. } if selected == [3, 2, 1, 0]));
+        Ok(())
+    }
 }
@@ -2,19 +2,52 @@ use crate::parser::macros::*;
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 
+macro_rules! extract_pattern {
+    () => {
+        concat!(r"EXTRACT\s*\(\s*\w+\s+FROM\s+", string_or_ident_pattern!(), r"\s*\)")
+    };
+}
+
+macro_rules! order_by_pattern {
+    () => {
+        concat!(
+            s_delimited!("(ORDER)", "BY", captured!(string_or_ident_pattern!())),
+            optional!(s1!(), captured!(unite!("ASC", "DESC")))
+        )
+    };
+}
+
 macro_rules! select_pattern {
     () => {
-        s_delimited!(
-            "(SELECT)",
-            unite!(captured!(r"\*"), commas!(captured!(string_or_ident_pattern!()))),
-            from_where_pattern!()
+        concat!(
+            s_delimited!(
+                "(SELECT)",
+                unite!(
+                    captured!(r"\*"),
+                    list!(unite!(
+                        extract_pattern!(),
+                        concat!(string_or_ident_pattern!(), json_path_pattern!())
+                    ))
+                ),
+                from_where_pattern!()
+            ),
+            optional!(s1!(), order_by_pattern!())
         )
     };
 }
 
+macro_rules! returning_pattern {
+    () => {
+        s_delimited!("(RETURNING)", unite!(captured!(r"\*"), list!(string_or_ident_pattern!())))
+    };
+}
+
 macro_rules! delete_pattern {
     () => {
-        s_delimited!("(DELETE)", from_where_pattern!())
+        concat!(
+            s_delimited!("(DELETE)", from_where_pattern!()),
+            optional!(s1!(), returning_pattern!())
+        )
     };
 }
 
@@ -24,7 +57,7 @@ macro_rules! create_table_pattern {
             "(CREATE)",
             "(TABLE)",
             captured!(string_or_ident_pattern!()),
-            maybe_s_delimited!(r"\(", commas!(attr_pattern!()), r"\)")
+            maybe_s_delimited!(r"\(", list!(attr_pattern!()), r"\)")
         )
     };
 }
@@ -36,7 +69,7 @@ macro_rules! create_index_pattern {
             "(INDEX)",
             "(ON)",
             captured!(string_or_ident_pattern!()),
-            maybe_s_delimited!(r"\(", commas!(captured!(string_or_ident_pattern!())), r"\)")
+            maybe_s_delimited!(r"\(", list!(string_or_ident_pattern!()), r"\)")
         )
     };
 }
@@ -49,19 +82,114 @@ macro_rules! create_named_index_pattern {
             captured!(optional!(string_or_ident_pattern!())),
             "(ON)",
             captured!(string_or_ident_pattern!()),
-            maybe_s_delimited!(r"\(", commas!(captured!(string_or_ident_pattern!())), r"\)")
+            maybe_s_delimited!(r"\(", list!(string_or_ident_pattern!()), r"\)")
+        )
+    };
+}
+
+macro_rules! row_pattern {
+    () => {
+        maybe_s_delimited!(r"\(", commas!(value_pattern!()), r"\)")
+    };
+}
+
+macro_rules! assignment_pattern {
+    ($value:expr) => {
+        concat!(string_or_ident_pattern!(), s!(), "=", s!(), $value)
+    };
+}
+
+macro_rules! update_pattern {
+    () => {
+        concat!(
+            s_delimited!(
+                "(UPDATE)",
+                captured!(string_or_ident_pattern!()),
+                "(SET)",
+                list!(assignment_pattern!(value_pattern!()))
+            ),
+            optional!(s1!(), where_pattern!()),
+            optional!(s1!(), returning_pattern!())
+        )
+    };
+}
+
+macro_rules! on_conflict_pattern {
+    () => {
+        s_delimited!(
+            "(ON)",
+            "(CONFLICT)",
+            concat!(
+                optional!(r"\(", s!(), captured!(string_or_ident_pattern!()), s!(), r"\)", s!()),
+                "(DO)"
+            ),
+            unite!(
+                "(NOTHING)",
+                s_delimited!(
+                    "(UPDATE)",
+                    "(SET)",
+                    list!(assignment_pattern!(unite!(
+                        value_pattern!(),
+                        concat!(r"EXCLUDED\.", string_or_ident_pattern!())
+                    )))
+                )
+            )
         )
     };
 }
 
 macro_rules! insert_pattern {
+    () => {
+        concat!(
+            s_delimited!("(INSERT)", "(INTO)", captured!(string_or_ident_pattern!())),
+            optional!(s!(), r"\(", s!(), list!(string_or_ident_pattern!()), s!(), r"\)"),
+            s1!(),
+            "(VALUES)",
+            s!(),
+            list!(row_pattern!()),
+            optional!(s1!(), on_conflict_pattern!()),
+            optional!(s1!(), returning_pattern!())
+        )
+    };
+}
+
+macro_rules! add_column_pattern {
+    () => {
+        concat!("(ADD)", s1!(), optional!("COLUMN", s1!()), captured!(attr_pattern!()))
+    };
+}
+
+macro_rules! drop_column_pattern {
+    () => {
+        concat!("(DROP)", s1!(), optional!("COLUMN", s1!()), captured!(string_or_ident_pattern!()))
+    };
+}
+
+macro_rules! rename_pattern {
+    () => {
+        unite!(
+            s_delimited!("(RENAME)", "(TO)", captured!(string_or_ident_pattern!())),
+            concat!(
+                "(RENAME)",
+                s1!(),
+                optional!("COLUMN", s1!()),
+                s_delimited!(
+                    captured!(string_or_ident_pattern!()),
+                    "(TO)",
+                    captured!(string_or_ident_pattern!())
+                )
+            )
+        )
+    };
+}
+
+macro_rules! alter_table_pattern {
     () => {
         s_delimited!(
-            "(INSERT)",
-            "(INTO)",
+            "(ALTER)",
+            "(TABLE)",
             captured!(string_or_ident_pattern!()),
-            "(VALUES)",
-            maybe_s_delimited!(r"\(", commas!(captured!(value_pattern!())), r"\)")
+            unite!(add_column_pattern!(), drop_column_pattern!(), rename_pattern!())
         )
     };
 }
@@ -72,6 +200,110 @@ macro_rules! drop_pattern {
     };
 }
 
+macro_rules! password_pattern {
+    () => {
+        concat!(optional!("WITH", s1!()), "(PASSWORD)", s1!(), captured!(string_pattern!()))
+    };
+}
+
+macro_rules! create_user_pattern {
+    () => {
+        s_delimited!("(CREATE)", "(USER)", captured!(r"\w+"), password_pattern!())
+    };
+}
+
+macro_rules! alter_user_pattern {
+    () => {
+        s_delimited!("(ALTER)", "(USER)", captured!(r"\w+"), password_pattern!())
+    };
+}
+
+macro_rules! drop_user_pattern {
+    () => {
+        s_delimited!("(DROP)", "(USER)", captured!(r"\w+"))
+    };
+}
+
+macro_rules! privilege_pattern {
+    () => {
+        unite!(
+            concat!("ALL", optional!(s1!(), "PRIVILEGES")),
+            "SELECT",
+            "INSERT",
+            "UPDATE",
+            "DELETE",
+            "CREATE",
+            "DROP"
+        )
+    };
+}
+
+macro_rules! grant_pattern {
+    () => {
+        s_delimited!(
+            "(GRANT)",
+            list!(privilege_pattern!()),
+            "(ON)",
+            captured!(unite!(r"\*", string_or_ident_pattern!())),
+            "(TO)",
+            captured!(r"\w+")
+        )
+    };
+}
+
+macro_rules! revoke_pattern {
+    () => {
+        s_delimited!(
+            "(REVOKE)",
+            list!(privilege_pattern!()),
+            "(ON)",
+            captured!(unite!(r"\*", string_or_ident_pattern!())),
+            "(FROM)",
+            captured!(r"\w+")
+        )
+    };
+}
+
+macro_rules! transaction_keyword {
+    () => {
+        optional!(s1!(), unite!("TRANSACTION", "WORK"))
+    };
+}
+
+macro_rules! begin_pattern {
+    () => {
+        unite!(concat!("(BEGIN)", transaction_keyword!()), concat!("(START)", s1!(), "TRANSACTION"))
+    };
+}
+
+macro_rules! commit_pattern {
+    () => {
+        concat!("(COMMIT)", transaction_keyword!())
+    };
+}
+
+macro_rules! rollback_pattern {
+    () => {
+        concat!(
+            "(ROLLBACK)",
+            transaction_keyword!(),
+            optional!(s1!(), "(TO)", s1!(), optional!("SAVEPOINT", s1!()), captured!(r"\w+"))
+        )
+    };
+}
+
+macro_rules! savepoint_pattern {
+    () => {
+        s_delimited!("(SAVEPOINT)", captured!(r"\w+"))
+    };
+}
+
+macro_rules! release_pattern {
+    () => {
+        concat!("(RELEASE)", s1!(), optional!("SAVEPOINT", s1!()), captured!(r"\w+"))
+    };
+}
+
 pub fn capture_command(input: &str) -> Vec<&str> {
     lazy_static! {
         static ref RE: Regex = RegexBuilder::new(anchored!(command!(unite!(
@@ -80,8 +312,20 @@ pub fn capture_command(input: &str) -> Vec<&str> {
             create_named_index_pattern!(),
             select_pattern!(),
             insert_pattern!(),
+            update_pattern!(),
             delete_pattern!(),
-            drop_pattern!()
+            drop_pattern!(),
+            alter_table_pattern!(),
+            create_user_pattern!(),
+            alter_user_pattern!(),
+            drop_user_pattern!(),
+            grant_pattern!(),
+            revoke_pattern!(),
+            begin_pattern!(),
+            commit_pattern!(),
+            rollback_pattern!(),
+            savepoint_pattern!(),
+            release_pattern!()
         ))))
         .case_insensitive(true)
         .build()
@@ -113,7 +357,16 @@ mod tests {
         assert_pattern(
             select_pattern!(),
             "select a, 'b' from c where d=2",
-            &["select", "a", "'b'", "from", "c", "where", "d", "=", "2"],
+            &["select", "a, 'b'", "from", "c", "where", "d", "=", "2"],
+        )
+    }
+
+    #[test]
+    fn test_select_many_columns() {
+        assert_pattern(
+            select_pattern!(),
+            "select a, b, c, d from e",
+            &["select", "a, b, c, d", "from", "e"],
         )
     }
 
@@ -127,7 +380,21 @@ mod tests {
         assert_pattern(
             create_table_pattern!(),
             "create table t ( id int primary key, \"name\" text )",
-            &["create", "table", "t", "id", "int primary key", "\"name\"", "text"],
+            &["create", "table", "t", "id int primary key, \"name\" text"],
+        );
+    }
+
+    #[test]
+    fn test_create_table_constraints() {
+        assert_pattern(
+            create_table_pattern!(),
+            "create table t (id int primary key not null, a text default 'x y' not null, b int null)",
+            &[
+                "create",
+                "table",
+                "t",
+                "id int primary key not null, a text default 'x y' not null, b int null",
+            ],
         );
     }
 
@@ -136,7 +403,7 @@ mod tests {
         assert_pattern(
             create_index_pattern!(),
             "create index on t (a, b)",
-            &["create", "index", "on", "t", "a", "b"],
+            &["create", "index", "on", "t", "a, b"],
         )
     }
 
@@ -145,7 +412,7 @@ mod tests {
         assert_pattern(
             create_named_index_pattern!(),
             "create index ab_ix on t (a, b)",
-            &["create", "index", "ab_ix", "on", "t", "a", "b"],
+            &["create", "index", "ab_ix", "on", "t", "a, b"],
         )
     }
 
@@ -154,10 +421,234 @@ mod tests {
         assert_pattern(
             insert_pattern!(),
             "insert into t values (NULL, 3)",
-            &["insert", "into", "t", "values", "NULL", "3"],
+            &["insert", "into", "t", "values", "(NULL, 3)"],
         )
     }
 
+    #[test]
+    fn test_blobs() {
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values (X'DEADbeef', x'')",
+            &["insert", "into", "t", "values", "(X'DEADbeef', x'')"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select * from t where b >= X'00ff'",
+            &["select", "*", "from", "t", "where", "b", ">=", "X'00ff'"],
+        );
+    }
+
+    #[test]
+    fn test_reals() {
+        assert_pattern(
+            create_table_pattern!(),
+            "create table t (a real, b double default -.5, c float)",
+            &["create", "table", "t", "a real, b double default -.5, c float"],
+        );
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values (1.5, -2e-3, 10., 'NaN')",
+            &["insert", "into", "t", "values", "(1.5, -2e-3, 10., 'NaN')"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select * from t where a<-1.25E10",
+            &["select", "*", "from", "t", "where", "a", "<", "-1.25E10"],
+        );
+    }
+
+    #[test]
+    fn test_booleans() {
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values (TRUE, false)",
+            &["insert", "into", "t", "values", "(TRUE, false)"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select * from t where active",
+            &["select", "*", "from", "t", "where", "active"],
+        );
+        assert_pattern(
+            delete_pattern!(),
+            "delete from t where \"active\" returning id",
+            &["delete", "from", "t", "where", "\"active\"", "returning", "id"],
+        );
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_pattern(
+            create_table_pattern!(),
+            "create table t (d date, t time, ts timestamp)",
+            &["create", "table", "t", "d date, t time, ts timestamp"],
+        );
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values ('2024-02-29', NOW())",
+            &["insert", "into", "t", "values", "('2024-02-29', NOW())"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select extract( year from d ), ts from t where ts < now()",
+            &["select", "extract( year from d ), ts", "from", "t", "where", "ts", "<", "now()"],
+        );
+    }
+
+    #[test]
+    fn test_decimals() {
+        assert_pattern(
+            create_table_pattern!(),
+            "create table t (a decimal(10, 2) not null, b NUMERIC (5), c decimal default 1.5)",
+            &[
+                "create",
+                "table",
+                "t",
+                "a decimal(10, 2) not null, b NUMERIC (5), c decimal default 1.5",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_json_paths() {
+        assert_pattern(
+            select_pattern!(),
+            "select doc->'user' ->> 'id', a from t where doc->'tags'->0 = '\"x\"'",
+            &[
+                "select",
+                "doc->'user' ->> 'id', a",
+                "from",
+                "t",
+                "where",
+                "doc->'tags'->0",
+                "=",
+                "'\"x\"'",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_null_checks() {
+        assert_pattern(
+            select_pattern!(),
+            "select a from t where b is null",
+            &["select", "a", "from", "t", "where", "b", "is", "null"],
+        );
+        assert_pattern(
+            delete_pattern!(),
+            "delete from t where doc->'a' IS NOT NULL",
+            &["delete", "from", "t", "where", "doc->'a'", "IS", "NOT", "NULL"],
+        );
+    }
+
+    #[test]
+    fn test_collations() {
+        assert_pattern(
+            create_table_pattern!(),
+            "create table t (a text collate nocase not null)",
+            &["create", "table", "t", "a text collate nocase not null"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select a from t where a = 'X' COLLATE binary",
+            &["select", "a", "from", "t", "where", "a", "=", "'X'", "COLLATE", "binary"],
+        );
+    }
+
+    #[test]
+    fn test_order_by() {
+        assert_pattern(
+            select_pattern!(),
+            "select a from t order by b",
+            &["select", "a", "from", "t", "order", "b"],
+        );
+        assert_pattern(
+            select_pattern!(),
+            "select * from t where a > 1 ORDER BY \"b\" desc",
+            &["select", "*", "from", "t", "where", "a", ">", "1", "ORDER", "\"b\"", "desc"],
+        );
+    }
+
+    #[test]
+    fn test_on_conflict() {
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values (1) on conflict do nothing",
+            &["insert", "into", "t", "values", "(1)", "on", "conflict", "do", "nothing"],
+        );
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t (id, a) values (1, 2) on conflict (id) do update set a = excluded.a, b=3",
+            &[
+                "insert",
+                "into",
+                "t",
+                "id, a",
+                "values",
+                "(1, 2)",
+                "on",
+                "conflict",
+                "id",
+                "do",
+                "update",
+                "set",
+                "a = excluded.a, b=3",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_insert_rows() {
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t (b, a) values (1, 'x'), ( 2,'y' ),(3, NULL)",
+            &["insert", "into", "t", "b, a", "values", "(1, 'x'), ( 2,'y' ),(3, NULL)"],
+        );
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t(a) values(1)",
+            &["insert", "into", "t", "a", "values", "(1)"],
+        )
+    }
+
+    #[test]
+    fn test_update() {
+        assert_pattern(
+            update_pattern!(),
+            "update t set a = 1, b='x' where id >= 3 returning *",
+            &["update", "t", "set", "a = 1, b='x'", "where", "id", ">=", "3", "returning", "*"],
+        );
+        assert_pattern(update_pattern!(), "UPDATE t SET a=NULL", &["UPDATE", "t", "SET", "a=NULL"]);
+        assert_pattern(update_pattern!(), "update t set a = excluded.a", &[]);
+    }
+
+    #[test]
+    fn test_returning() {
+        assert_pattern(
+            delete_pattern!(),
+            "delete from t returning id, a",
+            &["delete", "from", "t", "returning", "id, a"],
+        );
+        assert_pattern(
+            insert_pattern!(),
+            "insert into t values (1) on conflict do nothing returning *",
+            &[
+                "insert",
+                "into",
+                "t",
+                "values",
+                "(1)",
+                "on",
+                "conflict",
+                "do",
+                "nothing",
+                "returning",
+                "*",
+            ],
+        );
+    }
+
     #[test]
     fn test_delete() {
         assert_pattern(
@@ -171,4 +662,110 @@ mod tests {
     fn test_drop_table() {
         assert_pattern(drop_pattern!(), "drop table my_table", &["drop", "table", "my_table"])
     }
+
+    #[test]
+    fn test_alter_table() {
+        assert_pattern(
+            alter_table_pattern!(),
+            "alter table t add column c int not null default 5",
+            &["alter", "table", "t", "add", "c int not null default 5"],
+        );
+        assert_pattern(
+            alter_table_pattern!(),
+            "alter table t add c text",
+            &["alter", "table", "t", "add", "c text"],
+        );
+        assert_pattern(
+            alter_table_pattern!(),
+            "alter table t drop column c",
+            &["alter", "table", "t", "drop", "c"],
+        );
+        assert_pattern(
+            alter_table_pattern!(),
+            "alter table t rename column a to b",
+            &["alter", "table", "t", "rename", "a", "to", "b"],
+        );
+        assert_pattern(
+            alter_table_pattern!(),
+            "alter table t rename to u",
+            &["alter", "table", "t", "rename", "to", "u"],
+        );
+    }
+
+    #[test]
+    fn test_create_user() {
+        assert_pattern(
+            create_user_pattern!(),
+            "create user alice with password 'S3cret'",
+            &["create", "user", "alice", "password", "'S3cret'"],
+        );
+        assert_pattern(
+            create_user_pattern!(),
+            "create user alice password \"pw\"",
+            &["create", "user", "alice", "password", "\"pw\""],
+        )
+    }
+
+    #[test]
+    fn test_alter_user() {
+        assert_pattern(
+            alter_user_pattern!(),
+            "alter user alice with password 'new'",
+            &["alter", "user", "alice", "password", "'new'"],
+        )
+    }
+
+    #[test]
+    fn test_grant() {
+        assert_pattern(
+            grant_pattern!(),
+            "grant select, insert, delete on t to alice",
+            &["grant", "select, insert, delete", "on", "t", "to", "alice"],
+        );
+        assert_pattern(
+            grant_pattern!(),
+            "grant all privileges on * to alice",
+            &["grant", "all privileges", "on", "*", "to", "alice"],
+        )
+    }
+
+    #[test]
+    fn test_revoke() {
+        assert_pattern(
+            revoke_pattern!(),
+            "revoke drop on t from alice",
+            &["revoke", "drop", "on", "t", "from", "alice"],
+        )
+    }
+
+    #[test]
+    fn test_drop_user() {
+        assert_pattern(drop_user_pattern!(), "drop user alice", &["drop", "user", "alice"])
+    }
+
+    #[test]
+    fn test_transactions() {
+        assert_pattern(begin_pattern!(), "BEGIN", &["BEGIN"]);
+        assert_pattern(begin_pattern!(), "begin work", &["begin"]);
+        assert_pattern(begin_pattern!(), "start transaction", &["start"]);
+        assert_pattern(commit_pattern!(), "commit transaction", &["commit"]);
+        assert_pattern(rollback_pattern!(), "rollback", &["rollback"]);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        assert_pattern(
+            savepoint_pattern!(),
+            "SAVEPOINT before_import",
+            &["SAVEPOINT", "before_import"],
+        );
+        assert_pattern(
+            rollback_pattern!(),
+            "rollback work to savepoint a",
+            &["rollback", "to", "a"],
+        );
+        assert_pattern(rollback_pattern!(), "rollback to a", &["rollback", "to", "a"]);
+        assert_pattern(release_pattern!(), "release savepoint a", &["release", "a"]);
+        assert_pattern(release_pattern!(), "release a", &["release", "a"]);
+    }
 }
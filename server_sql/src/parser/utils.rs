@@ -1,9 +1,65 @@
 use crate::{
-    database::{Attribute, AttributeType, Comparison, DataAttribute, DatabaseTable},
+    auth::Privilege,
+    database::{
+        AlterTable, Assignments, Attribute, AttributeType, Collation, Comparison, DataAttribute,
+        DataAttributes, DatabaseTable, DatePart, Decimal, Extracted, Extraction, Float, JsonPath,
+        JsonStep, OnConflict, SetValue, MAX_PRECISION,
+    },
     error::UserError,
 };
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use std::collections::HashMap;
 
+use super::QUOTES;
+
+/// Lowercases everything outside of quoted strings
+pub fn lowercase_unquoted(input: &str) -> String {
+    let mut quote = None;
+    input
+        .chars()
+        .map(|c| match quote {
+            Some(q) if c == q => {
+                quote = None;
+                c
+            }
+            Some(_) => c,
+            None if QUOTES.contains(&c) => {
+                quote = Some(c);
+                c
+            }
+            None => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Splits a list captured by `list!` on commas outside of quotes and parentheses
+pub fn split_list(list: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (ix, c) in list.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    items.push(list[start..ix].trim());
+                    start = ix + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    items.push(list[start..].trim());
+    // Trailing commas are allowed
+    items.retain(|item| !item.is_empty());
+    items
+}
+
 pub fn get_col_to_ix_map(table: &DatabaseTable) -> HashMap<&str, usize> {
     table.attributes.iter().enumerate().map(|(ix, attr)| (attr.name.as_str(), ix)).collect()
 }
@@ -19,33 +75,350 @@ pub fn parse_cols(cols: &[&str], table: &DatabaseTable) -> Result<Vec<usize>, Us
     }
 }
 
+/// Selected columns and the positions in a selected row of the parts given by
+/// `EXTRACT(part FROM column)` or of the values found by JSON paths
+pub fn parse_selected(
+    cols: &[&str],
+    table: &DatabaseTable,
+) -> Result<(Vec<usize>, Extracted), UserError> {
+    let mut extracted = vec![];
+    let mut names = vec![];
+    for (ix, col) in cols.iter().enumerate() {
+        match col.strip_prefix("extract").map(str::trim_start) {
+            Some(args) if args.starts_with('(') => {
+                let (name, part) = parse_extract(args, table)?;
+                names.push(name);
+                extracted.push((ix, Extraction::Part(part)));
+            }
+            _ => match parse_json_path(col)? {
+                Some((name, path)) => {
+                    json_column(name, table)?;
+                    names.push(name);
+                    extracted.push((ix, Extraction::Path(path)));
+                }
+                None => names.push(col),
+            },
+        }
+    }
+    Ok((parse_cols(&names, table)?, extracted))
+}
+
+fn parse_extract<'a>(
+    args: &'a str,
+    table: &DatabaseTable,
+) -> Result<(&'a str, DatePart), UserError> {
+    let args = args.strip_prefix('(').and_then(|args| args.strip_suffix(')'));
+    let [part, "from", col] = split_words(args.ok_or(UserError::SyntaxError)?)[..] else {
+        return Err(UserError::SyntaxError);
+    };
+    let part = match part {
+        "year" => DatePart::Year,
+        "month" => DatePart::Month,
+        "day" => DatePart::Day,
+        "hour" => DatePart::Hour,
+        "minute" => DatePart::Minute,
+        "second" => DatePart::Second,
+        "epoch" => DatePart::Epoch,
+        _ => return Err(UserError::Other("Unknown date part")),
+    };
+    let col = parse_string(col).unwrap_or(col);
+    let (attr_pos, _) = describe_col(col, table)?;
+    if !part.applies_to(&table.attributes[attr_pos].attribute_type) {
+        return Err(UserError::Other("Column has no such date part"));
+    }
+    Ok((col, part))
+}
+
+/// Splits `column->'key'->>0` into the column and the path, None when there is no path
+pub fn parse_json_path(expr: &str) -> Result<Option<(&str, JsonPath)>, UserError> {
+    let col_end = match expr.chars().next() {
+        Some(quote) if QUOTES.contains(&quote) => match expr[1..].find(quote) {
+            Some(end) => end + 2,
+            None => return Ok(None),
+        },
+        _ => expr.find("->").unwrap_or(expr.len()),
+    };
+    let (col, rest) = expr.split_at(col_end);
+    let mut rest = rest.trim_start();
+    if !rest.starts_with("->") {
+        return Ok(None);
+    }
+    let mut path = JsonPath { steps: vec![], unwrap: false };
+    while !rest.is_empty() {
+        // Nothing follows the step taking the value out of JSON
+        if path.unwrap {
+            return Err(UserError::SyntaxError);
+        }
+        rest = rest.strip_prefix("->").ok_or(UserError::SyntaxError)?;
+        if let Some(unwrapped) = rest.strip_prefix('>') {
+            path.unwrap = true;
+            rest = unwrapped;
+        }
+        rest = rest.trim_start();
+        let step_end = match rest.strip_prefix('\'') {
+            Some(key) => key.find('\'').ok_or(UserError::SyntaxError)? + 2,
+            None => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()),
+        };
+        let (step, next) = rest.split_at(step_end);
+        path.steps.push(match parse_string(step) {
+            Ok(key) => JsonStep::Key(key.to_owned()),
+            Err(_) => JsonStep::Index(step.parse().map_err(|_| UserError::SyntaxError)?),
+        });
+        rest = next.trim_start();
+    }
+    let col = col.trim_end();
+    Ok(Some((parse_string(col).unwrap_or(col), path)))
+}
+
+/// Position of a JSON column, paths lead only into JSON
+fn json_column(col: &str, table: &DatabaseTable) -> Result<usize, UserError> {
+    let (attr_pos, _) = describe_col(col, table)?;
+    if table.attributes[attr_pos].attribute_type != AttributeType::Json {
+        return Err(UserError::Other("Paths are only supported for JSON columns"));
+    }
+    Ok(attr_pos)
+}
+
+/// Columns an insert gives values for, all columns but the primary key by default,
+/// the primary key is generated unless it is listed
+pub fn parse_insert_columns(
+    cols: Option<&str>,
+    table: &DatabaseTable,
+) -> Result<Vec<usize>, UserError> {
+    let Some(cols) = cols else {
+        let is_pk = |ix: &usize| table.attributes[*ix].attribute_type == AttributeType::Id;
+        return Ok((0..table.attributes.len()).filter(|ix| !is_pk(ix)).collect());
+    };
+    let columns = parse_cols(&split_list(cols), table)?;
+    for (ix, col) in columns.iter().enumerate() {
+        if columns[..ix].contains(col) {
+            return Err(UserError::Other("Column specified more than once"));
+        }
+    }
+    Ok(columns)
+}
+
+/// Values are given for `columns` in order, the other columns get their default
 pub fn parse_values(
     values: &[&str],
+    columns: &[usize],
     table: &DatabaseTable,
 ) -> Result<Vec<DataAttribute>, UserError> {
-    if values.len() > table.attributes.len() - 1 {
-        Err(UserError::Other("Too many values"))
-    } else {
-        (&[""])
+    if values.len() > columns.len() {
+        return Err(UserError::Other("Too many values"));
+    }
+    let mut row = table
+        .attributes
+        .iter()
+        .map(|attr| match attr.attribute_type {
+            AttributeType::Id => DataAttribute::NoneId,
+            _ => attr.default_value(),
+        })
+        .collect::<Vec<_>>();
+    for (val, col) in values.iter().zip(columns) {
+        row[*col] = parse_value(val, &table.attributes[*col].attribute_type)?;
+    }
+    Ok(row)
+}
+
+/// Parses the rows of `VALUES (..), (..)`, a column list needs a value for every column
+pub fn parse_rows(
+    rows: &str,
+    cols: Option<&str>,
+    table: &DatabaseTable,
+) -> Result<Vec<DataAttributes>, UserError> {
+    let columns = parse_insert_columns(cols, table)?;
+    split_list(rows)
+        .iter()
+        .map(|row| {
+            let row = row.strip_prefix('(').and_then(|row| row.strip_suffix(')'));
+            let values = split_list(row.ok_or(UserError::SyntaxError)?);
+            if cols.is_some() && values.len() < columns.len() {
+                return Err(UserError::Other("Too few values"));
+            }
+            Ok(DataAttributes { attributes: parse_values(&values, &columns, table)? })
+        })
+        .collect()
+}
+
+/// Parses a literal stored in a column, primary keys have to be integers
+pub fn parse_value(val: &str, attribute_type: &AttributeType) -> Result<DataAttribute, UserError> {
+    Ok(match attribute_type {
+        AttributeType::Id => {
+            DataAttribute::Id(val.parse().map_err(|_| UserError::Other("Bad type"))?)
+        }
+        _ if val == "null" => DataAttribute::None,
+        _ if val == "now()" => current(attribute_type)?,
+        AttributeType::String => DataAttribute::String(parse_string(val)?.to_owned()),
+        AttributeType::Number => DataAttribute::Number(parse_i64(val)?),
+        AttributeType::Data => DataAttribute::Data(parse_blob(val)?),
+        AttributeType::Real => DataAttribute::Real(parse_real(val)?),
+        AttributeType::Bool => DataAttribute::Bool(parse_bool(val)?),
+        AttributeType::Date | AttributeType::Time | AttributeType::Timestamp => {
+            parse_temporal(parse_string(val)?, attribute_type)?
+        }
+        AttributeType::Decimal { precision, scale } => {
+            DataAttribute::Decimal(parse_decimal(val, *precision, *scale)?)
+        }
+        AttributeType::Json => DataAttribute::Json(parse_json(parse_string(val)?)?),
+    })
+}
+
+/// Validates JSON, it is kept in its compact form
+pub fn parse_json(text: &str) -> Result<String, UserError> {
+    let value = serde_json::from_str::<serde_json::Value>(text);
+    value.map(|value| value.to_string()).map_err(|_| UserError::Other("Invalid JSON"))
+}
+
+/// Decimal rounded to the scale of the column, with no more digits than its precision
+pub fn parse_decimal(val: &str, precision: u32, scale: u32) -> Result<Decimal, UserError> {
+    let decimal = val.parse::<Decimal>().map_err(|_| UserError::SyntaxError)?;
+    match decimal.rescale(scale) {
+        Some(decimal) if decimal.fits(precision) => Ok(decimal),
+        _ => Err(UserError::Other("Decimal does not fit the precision of the column")),
+    }
+}
+
+/// Value of `NOW()` in a column, in UTC
+fn current(attribute_type: &AttributeType) -> Result<DataAttribute, UserError> {
+    let now = Utc::now().naive_utc();
+    match attribute_type {
+        AttributeType::Date => Ok(DataAttribute::Date(now.date())),
+        AttributeType::Time => Ok(DataAttribute::Time(now.time())),
+        AttributeType::Timestamp => Ok(DataAttribute::Timestamp(now)),
+        _ => Err(UserError::Other("Bad type")),
+    }
+}
+
+/// ISO 8601 date, time or timestamp, timestamps with an offset are converted to UTC
+pub fn parse_temporal(
+    text: &str,
+    attribute_type: &AttributeType,
+) -> Result<DataAttribute, UserError> {
+    let parsed = match attribute_type {
+        AttributeType::Date => parse_date(text).map(DataAttribute::Date),
+        AttributeType::Time => ["%H:%M:%S%.f", "%H:%M"]
             .iter()
-            .chain(values.iter())
-            .zip(table.attributes.iter())
-            .map(|(val, attr)| {
-                Ok(match attr.attribute_type {
-                    AttributeType::Id => {
-                        if *val != "null" {
-                            DataAttribute::NoneId
-                        } else {
-                            return Err(UserError::Other("Bad type"));
-                        }
+            .find_map(|format| NaiveTime::parse_from_str(text, format).ok())
+            .map(DataAttribute::Time),
+        AttributeType::Timestamp => parse_timestamp(text).map(DataAttribute::Timestamp),
+        _ => unreachable!(),
+    };
+    parsed.ok_or(UserError::Other("Invalid date or time"))
+}
+
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
+
+// A date alone is its midnight
+fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Some(timestamp.naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| Some(parse_date(text)?.and_time(NaiveTime::default())))
+}
+
+pub fn parse_bool(val: &str) -> Result<bool, UserError> {
+    match val {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(UserError::Other("Bad type")),
+    }
+}
+
+/// Decimal literal, NaN and infinities are given as strings like in `'-Infinity'`
+pub fn parse_real(val: &str) -> Result<Float, UserError> {
+    let real = match parse_string(val) {
+        Ok(special) => match special.to_ascii_lowercase().as_str() {
+            "nan" => f64::NAN,
+            "infinity" => f64::INFINITY,
+            "-infinity" => f64::NEG_INFINITY,
+            _ => return Err(UserError::Other("Bad type")),
+        },
+        Err(_) => val.parse().map_err(|_| UserError::SyntaxError)?,
+    };
+    Ok(Float(real))
+}
+
+/// Hex literal `X'DEADBEEF'`, two digits for every byte
+pub fn parse_blob(val: &str) -> Result<Vec<u8>, UserError> {
+    let Some(hex) = val.strip_prefix('x') else {
+        return Err(UserError::Other("Bad type"));
+    };
+    let hex = parse_string(hex)?;
+    if !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(UserError::Other("Invalid hex digit"));
+    }
+    if hex.len() % 2 != 0 {
+        return Err(UserError::Other("Odd number of hex digits"));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|ix| u8::from_str_radix(&hex[ix..ix + 2], 16).unwrap())
+        .collect())
+}
+
+pub fn parse_added_column(attr: &str) -> Result<AlterTable, UserError> {
+    let attribute = parse_attribute(attr)?;
+    if attribute.attribute_type == AttributeType::Id {
+        return Err(UserError::Other("Primary keys cannot be added"));
+    }
+    Ok(AlterTable::AddColumn { attribute })
+}
+
+/// Assignments `column = value`, the value may refer to a column of the row proposed
+/// for insertion by `excluded.column`
+pub fn parse_assignments(
+    assignments: &[&str],
+    table: &DatabaseTable,
+) -> Result<Assignments, UserError> {
+    assignments
+        .iter()
+        .map(|assignment| {
+            let (col, value) = assignment.split_once('=').ok_or(UserError::SyntaxError)?;
+            let (pos, is_pk) = describe_col(col.trim(), table)?;
+            if is_pk {
+                return Err(UserError::Other("Primary keys cannot be updated"));
+            }
+            let attribute_type = &table.attributes[pos].attribute_type;
+            let value = match value.trim().strip_prefix("excluded.") {
+                Some(excluded) => {
+                    let (excluded, _) = describe_col(excluded, table)?;
+                    if table.attributes[excluded].attribute_type != *attribute_type {
+                        return Err(UserError::Other("Bad type"));
                     }
-                    _ if *val == "null" => DataAttribute::None,
-                    AttributeType::String => DataAttribute::String(parse_string(val)?.to_owned()),
-                    AttributeType::Number => DataAttribute::Number(parse_i64(val)?),
-                    AttributeType::Data => unimplemented!(),
-                })
-            })
-            .collect()
+                    SetValue::Excluded(excluded)
+                }
+                None => SetValue::Value(parse_value(value.trim(), attribute_type)?),
+            };
+            Ok((pos, value))
+        })
+        .collect()
+}
+
+/// Tokens following the rows of an insert, conflicts are detected on the primary key only
+pub fn parse_on_conflict(clause: &[&str], table: &DatabaseTable) -> Result<OnConflict, UserError> {
+    let (target, action) = match clause {
+        [] => return Ok(OnConflict::Error),
+        ["on", "conflict", "do", action @ ..] => (None, action),
+        ["on", "conflict", target, "do", action @ ..] => (Some(target), action),
+        _ => return Err(UserError::SyntaxError),
+    };
+    if let Some(target) = target {
+        if !describe_col(target, table)?.1 {
+            return Err(UserError::Other("Conflicts are only detected on the primary key"));
+        }
+    }
+    match action {
+        ["nothing"] => Ok(OnConflict::Nothing),
+        ["update", "set", assignments] => {
+            Ok(OnConflict::Update(parse_assignments(&split_list(assignments), table)?))
+        }
+        _ => Err(UserError::SyntaxError),
     }
 }
 
@@ -53,22 +426,61 @@ pub fn parse_i64(val: &str) -> Result<i64, UserError> {
     val.parse().map_err(|_| UserError::SyntaxError)
 }
 
+/// Comparison of a column with a literal, strings are compared by the given collation or
+/// else the one of the column
 pub fn parse_comparison(
     lhs: &str,
     cmp: &str,
     rhs: &str,
+    collation: Option<&str>,
     table: &DatabaseTable,
 ) -> Result<(usize, Comparison), UserError> {
-    let terms = [lhs, rhs].map(parse_comparison_term);
+    let collation = collation.map(parse_collation).transpose()?;
+    let literal = [lhs, rhs];
+    let [lhs, rhs] = [lhs, rhs].map(parse_comparison_term);
+    let terms = [lhs?, rhs?];
     let flipped = matches!(terms[0], CmpTerm::Val(_));
-    let (attr_pos, data_attr) = match terms {
-        [CmpTerm::Ident(col), CmpTerm::Val(val)] | [CmpTerm::Val(val), CmpTerm::Ident(col)] => {
-            let (attr_pos, is_pk) = describe_col(col, table)?;
+    let literal = literal[!flipped as usize];
+    let (attr_pos, path, collation, data_attr) = match terms {
+        [CmpTerm::Path(col, path), CmpTerm::Val(val)]
+        | [CmpTerm::Val(val), CmpTerm::Path(col, path)] => {
+            let attr_pos = json_column(col, table)?;
             let val = match val {
-                DataAttribute::Number(num) if is_pk => DataAttribute::Id(num),
+                DataAttribute::String(text) if !path.unwrap => {
+                    DataAttribute::Json(parse_json(&text)?)
+                }
                 x => x,
             };
-            (attr_pos, val)
+            (attr_pos, Some(path), collation.unwrap_or_default(), val)
+        }
+        [CmpTerm::Ident(col), CmpTerm::Val(val)] | [CmpTerm::Val(val), CmpTerm::Ident(col)] => {
+            let (attr_pos, _) = describe_col(col, table)?;
+            // Literals are converted to the type of the column where it is unambiguous
+            let val = match (val, &table.attributes[attr_pos].attribute_type) {
+                (DataAttribute::Number(num), AttributeType::Id) => DataAttribute::Id(num),
+                (
+                    DataAttribute::String(text),
+                    attribute_type @ (AttributeType::Date
+                    | AttributeType::Time
+                    | AttributeType::Timestamp),
+                ) => parse_temporal(&text, attribute_type)?,
+                (DataAttribute::Timestamp(now), AttributeType::Time) => {
+                    DataAttribute::Time(now.time())
+                }
+                // Compared exactly instead of as the nearest real
+                (DataAttribute::Real(_), AttributeType::Decimal { .. }) => DataAttribute::Decimal(
+                    literal.parse().map_err(|_| UserError::Other("Invalid decimal"))?,
+                ),
+                (DataAttribute::String(text), AttributeType::Json) => {
+                    DataAttribute::Json(parse_json(&text)?)
+                }
+                (x, _) => x,
+            };
+            if !comparable(&val, &table.attributes[attr_pos].attribute_type) {
+                return Err(UserError::Other("Compared value does not match the column type"));
+            }
+            let collation = collation.unwrap_or(table.attributes[attr_pos].collation);
+            (attr_pos, None, collation, val)
         }
         _ => {
             return Err(UserError::Other(
@@ -76,6 +488,14 @@ pub fn parse_comparison(
             ))
         }
     };
+    let data_attr = match data_attr {
+        DataAttribute::String(text) => DataAttribute::String(collation.fold(&text)),
+        DataAttribute::None => DataAttribute::None,
+        _ if collation != Collation::Binary => {
+            return Err(UserError::Other("Collations only apply to text"))
+        }
+        x => x,
+    };
     let cmp = match (cmp, flipped) {
         (">", false) | ("<", true) => Comparison::Higher(data_attr),
         ("<", false) | (">", true) => Comparison::Lower(data_attr),
@@ -85,7 +505,67 @@ pub fn parse_comparison(
         ("<=", false) | (">=", true) => Comparison::LowerOrEqual(data_attr),
         _ => unreachable!(),
     };
-    Ok((attr_pos, cmp))
+    let cmp = match collation {
+        Collation::Binary => cmp,
+        collation => Comparison::Collated(collation, Box::new(cmp)),
+    };
+    match path {
+        Some(path) => Ok((attr_pos, Comparison::Path(path, Box::new(cmp)))),
+        None => Ok((attr_pos, cmp)),
+    }
+}
+
+/// Whether a literal converted for the column can be compared with its values, numbers
+/// are comparable across the numeric types and dates with timestamps
+fn comparable(val: &DataAttribute, attribute_type: &AttributeType) -> bool {
+    match (val, attribute_type) {
+        (DataAttribute::None, _) => true,
+        (DataAttribute::Number(_) | DataAttribute::Real(_) | DataAttribute::Decimal(_), _) => {
+            matches!(
+                attribute_type,
+                AttributeType::Number | AttributeType::Real | AttributeType::Decimal { .. }
+            )
+        }
+        (DataAttribute::Date(_) | DataAttribute::Timestamp(_), _) => {
+            matches!(attribute_type, AttributeType::Date | AttributeType::Timestamp)
+        }
+        (DataAttribute::Id(_), AttributeType::Id)
+        | (DataAttribute::String(_), AttributeType::String)
+        | (DataAttribute::Data(_), AttributeType::Data)
+        | (DataAttribute::Bool(_), AttributeType::Bool)
+        | (DataAttribute::Time(_), AttributeType::Time)
+        | (DataAttribute::Json(_), AttributeType::Json) => true,
+        _ => false,
+    }
+}
+
+/// `IS NULL` and `IS NOT NULL` on a column or a path into a JSON column
+pub fn parse_null_check(
+    col: &str,
+    negated: bool,
+    table: &DatabaseTable,
+) -> Result<(usize, Comparison), UserError> {
+    let cmp = if negated { Comparison::IsNotNull } else { Comparison::IsNull };
+    match parse_comparison_term(col)? {
+        CmpTerm::Ident(col) => Ok((describe_col(col, table)?.0, cmp)),
+        CmpTerm::Path(col, path) => {
+            Ok((json_column(col, table)?, Comparison::Path(path, Box::new(cmp))))
+        }
+        CmpTerm::Val(_) => Err(UserError::Other("Only columns can be checked for NULL")),
+    }
+}
+
+/// `WHERE active` selects the rows where a boolean column is true
+pub fn parse_predicate_column(
+    col: &str,
+    table: &DatabaseTable,
+) -> Result<(usize, Comparison), UserError> {
+    let col = parse_string(col).unwrap_or(col);
+    let (attr_pos, _) = describe_col(col, table)?;
+    if table.attributes[attr_pos].attribute_type != AttributeType::Bool {
+        return Err(UserError::Other("Only boolean columns can be used as a condition"));
+    }
+    Ok((attr_pos, Comparison::Equal(DataAttribute::Bool(true))))
 }
 
 pub fn describe_col(col: &str, table: &DatabaseTable) -> Result<(usize, bool), UserError> {
@@ -105,21 +585,32 @@ pub fn describe_col(col: &str, table: &DatabaseTable) -> Result<(usize, bool), U
 
 pub enum CmpTerm<'a> {
     Ident(&'a str),
+    Path(&'a str, JsonPath),
     Val(DataAttribute),
 }
 
-pub fn parse_comparison_term(term: &str) -> CmpTerm<'_> {
-    if term == "null" {
+pub fn parse_comparison_term(term: &str) -> Result<CmpTerm<'_>, UserError> {
+    Ok(if let Some((col, path)) = parse_json_path(term)? {
+        CmpTerm::Path(col, path)
+    } else if term == "null" {
         CmpTerm::Val(DataAttribute::None)
+    } else if term == "now()" {
+        CmpTerm::Val(DataAttribute::Timestamp(Utc::now().naive_utc()))
+    } else if let Ok(boolean) = parse_bool(term) {
+        CmpTerm::Val(DataAttribute::Bool(boolean))
     } else if term.starts_with('\'') && term.ends_with('\'') {
-        CmpTerm::Val(DataAttribute::String(parse_string(term).unwrap().to_owned()))
+        CmpTerm::Val(DataAttribute::String(parse_string(term)?.to_owned()))
+    } else if term.starts_with("x'") && term.ends_with('\'') {
+        CmpTerm::Val(DataAttribute::Data(parse_blob(term)?))
     } else if term.starts_with('"') && term.ends_with('"') {
-        CmpTerm::Ident(parse_string(term).unwrap())
+        CmpTerm::Ident(parse_string(term)?)
     } else if let Ok(num) = term.parse::<i64>() {
         CmpTerm::Val(DataAttribute::Number(num))
+    } else if term.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+        CmpTerm::Val(DataAttribute::Real(parse_real(term)?))
     } else {
         CmpTerm::Ident(term)
-    }
+    })
 }
 
 pub fn parse_string(val: &str) -> Result<&str, UserError> {
@@ -132,26 +623,172 @@ pub fn parse_string(val: &str) -> Result<&str, UserError> {
     }
 }
 
-pub fn parse_attr_type(attr_type: &str) -> Result<AttributeType, UserError> {
-    let (type_name, is_pk) = if let Some(ix) = attr_type.find("primary key") {
-        (&attr_type[..ix - 1], true)
-    } else {
-        (attr_type, false)
-    };
+pub fn parse_attr_type(type_name: &str, is_pk: bool) -> Result<AttributeType, UserError> {
+    let decimal = type_name.strip_prefix("decimal").or(type_name.strip_prefix("numeric"));
     match type_name {
         "int" | "integer" => Ok(if is_pk { AttributeType::Id } else { AttributeType::Number }),
         _ if is_pk => Err(UserError::Other("Only integers supported for primary keys")),
         "string" | "varchar" | "text" => Ok(AttributeType::String),
         "data" | "blob" => Ok(AttributeType::Data),
+        "real" | "double" | "float" => Ok(AttributeType::Real),
+        "boolean" | "bool" => Ok(AttributeType::Bool),
+        "date" => Ok(AttributeType::Date),
+        "time" => Ok(AttributeType::Time),
+        "timestamp" => Ok(AttributeType::Timestamp),
+        "json" => Ok(AttributeType::Json),
+        _ if decimal.is_some() => parse_decimal_type(decimal.unwrap_or_default()),
         _ => unreachable!(),
     }
 }
 
+/// Arguments `(precision, scale)` of a decimal type, the scale is zero by default
+fn parse_decimal_type(args: &str) -> Result<AttributeType, UserError> {
+    let args = args.trim().strip_prefix('(').and_then(|args| args.strip_suffix(')'));
+    let args = args.map(|args| args.split(',').map(|arg| parse_i64(arg.trim())).collect());
+    let args: Option<Vec<i64>> = args.transpose()?;
+    let (precision, scale) = match args.as_deref() {
+        None => (MAX_PRECISION as i64, 0),
+        Some([precision]) => (*precision, 0),
+        Some([precision, scale]) => (*precision, *scale),
+        Some(_) => return Err(UserError::SyntaxError),
+    };
+    if !(1..=MAX_PRECISION as i64).contains(&precision) || !(0..=precision).contains(&scale) {
+        return Err(UserError::Other("Invalid precision or scale of decimal"));
+    }
+    Ok(AttributeType::Decimal { precision: precision as u32, scale: scale as u32 })
+}
+
+/// Splits on whitespace outside of quoted strings
+fn split_words(input: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut quote = None;
+    let mut start = None;
+    for (ix, c) in input.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    words.push(&input[start..ix]);
+                }
+            }
+            None => {
+                if QUOTES.contains(&c) {
+                    quote = Some(c);
+                }
+                start.get_or_insert(ix);
+            }
+        }
+    }
+    if let Some(start) = start {
+        words.push(&input[start..]);
+    }
+    words
+}
+
+/// Splits a column definition into the column name and the rest of the definition
+fn split_attr(attr: &str) -> Result<(&str, &str), UserError> {
+    let name_end = match attr.chars().next() {
+        Some(quote) if QUOTES.contains(&quote) => {
+            attr[1..].find(quote).ok_or(UserError::SyntaxError)? + 2
+        }
+        _ => attr.find(char::is_whitespace).ok_or(UserError::SyntaxError)?,
+    };
+    Ok((&attr[..name_end], attr[name_end..].trim()))
+}
+
+/// Parses a column definition, the type may be followed by constraints in any order
+pub fn parse_attribute(attr: &str) -> Result<Attribute, UserError> {
+    let (name, definition) = split_attr(attr)?;
+    // Arguments of the type like in `decimal(10, 2)` may contain spaces
+    let word_end = definition.find(char::is_whitespace).unwrap_or(definition.len());
+    let type_end = if definition[..word_end].contains('(')
+        || definition[word_end..].trim_start().starts_with('(')
+    {
+        definition.find(')').ok_or(UserError::SyntaxError)? + 1
+    } else {
+        word_end
+    };
+    let (type_name, constraints) = definition.split_at(type_end);
+    let words = split_words(constraints);
+    let mut constraints = &words[..];
+    let (mut is_pk, mut not_null, mut default) = (false, false, None);
+    let mut collation = Collation::Binary;
+    loop {
+        constraints = match constraints {
+            ["primary", "key", rest @ ..] => {
+                is_pk = true;
+                rest
+            }
+            ["not", "null", rest @ ..] => {
+                not_null = true;
+                rest
+            }
+            ["null", rest @ ..] => {
+                not_null = false;
+                rest
+            }
+            ["default", value, rest @ ..] => {
+                default = Some(*value);
+                rest
+            }
+            ["collate", name, rest @ ..] => {
+                collation = parse_collation(name)?;
+                rest
+            }
+            [] => break,
+            _ => return Err(UserError::SyntaxError),
+        };
+    }
+    let attribute_type = parse_attr_type(type_name, is_pk)?;
+    if is_pk && default.is_some() {
+        return Err(UserError::Other("Primary keys cannot have a default"));
+    }
+    if default == Some("now()") {
+        return Err(UserError::Other("Defaults have to be constant"));
+    }
+    if collation != Collation::Binary && attribute_type != AttributeType::String {
+        return Err(UserError::Other("Collations only apply to text"));
+    }
+    let default = default.map(|val| parse_value(val, &attribute_type)).transpose()?;
+    Ok(Attribute { name: name.to_string(), attribute_type, not_null, default, collation })
+}
+
+fn parse_collation(name: &str) -> Result<Collation, UserError> {
+    match name {
+        "binary" => Ok(Collation::Binary),
+        "nocase" => Ok(Collation::NoCase),
+        "unicode" => Ok(Collation::Unicode),
+        _ => Err(UserError::Other("Unknown collation")),
+    }
+}
+
 pub fn parse_attributes(attrs: &[&str]) -> Result<Vec<Attribute>, UserError> {
-    attrs
-        .chunks_exact(2)
-        .map(|pair| {
-            Ok(Attribute { name: pair[0].to_string(), attribute_type: parse_attr_type(pair[1])? })
-        })
-        .collect()
+    attrs.iter().map(|attr| parse_attribute(attr)).collect()
+}
+
+pub fn parse_privileges(privileges: &[&str]) -> Result<Vec<Privilege>, UserError> {
+    let mut parsed = vec![];
+    for privilege in privileges {
+        match privilege.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["all"] | ["all", "privileges"] => parsed.extend(Privilege::ALL),
+            ["select"] => parsed.push(Privilege::Select),
+            ["insert"] => parsed.push(Privilege::Insert),
+            ["update"] => parsed.push(Privilege::Update),
+            ["delete"] => parsed.push(Privilege::Delete),
+            ["create"] => parsed.push(Privilege::Create),
+            ["drop"] => parsed.push(Privilege::Drop),
+            _ => return Err(UserError::Other("Unknown privilege")),
+        }
+    }
+    Ok(parsed)
+}
+
+/// `*` grants on all tables
+pub fn parse_grant_target(table: &str) -> Option<String> {
+    if table == "*" {
+        None
+    } else {
+        Some(table.to_string())
+    }
 }
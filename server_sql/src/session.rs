@@ -0,0 +1,14 @@
+use crate::transaction::Transaction;
+
+/// State of a single authenticated client connection
+pub struct Session {
+    pub user: String,
+    // Transaction started by BEGIN, statements outside of it are committed on their own
+    pub transaction: Option<Transaction>,
+}
+
+impl Session {
+    pub fn new(user: String) -> Self {
+        Session { user, transaction: None }
+    }
+}
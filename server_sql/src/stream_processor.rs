@@ -1,5 +1,13 @@
-use crate::database::Database;
+use crate::auth::Privilege;
+use crate::data::snapshot_path;
+use crate::database::DatabaseResponse;
+use crate::database::{Change, Database, FlushSummary, OnConflict};
+use crate::error::{DatabaseError, UserError};
+use crate::parser::Command;
+use crate::session::Session;
+use crate::transaction::Transaction;
 use ron::ser::to_string;
+use tokio::sync::RwLock;
 
 use client_sql::Command as Action;
 use client_sql::CommandType;
@@ -7,44 +15,926 @@ use client_sql::CommandType;
 #[derive(Default)]
 pub struct StreamProcessor {
     database: Database,
+    // Held from parsing a statement to executing it, exclusively by statements changing
+    // the schema, so column positions resolved by the parser stay valid
+    catalog: RwLock<()>,
 }
 
 impl StreamProcessor {
-    pub async fn process_str(&self, str_command: String) -> anyhow::Result<String> {
+    /// Writes all tables to the data directory, they are loaded again by `load`
+    pub async fn flush(&self) -> std::io::Result<FlushSummary> {
+        self.database.flush(&snapshot_path()).await
+    }
+
+    pub async fn load(&self) -> std::io::Result<FlushSummary> {
+        self.database.load(&snapshot_path()).await
+    }
+
+    pub async fn collect_garbage(&self) -> usize {
+        self.database.collect_garbage().await
+    }
+
+    /// Creates the superuser, or resets its password when it was loaded with the tables
+    pub async fn add_superuser(&self, name: &str, password: &str) -> Result<(), DatabaseError> {
+        self.database.users.set_superuser(&name.to_ascii_lowercase(), password).await;
+        Ok(())
+    }
+
+    /// Authenticates the first message of a connection, which has to be a login
+    pub async fn login(&self, str_command: String) -> anyhow::Result<(Session, String)> {
+        let deserialized: Action = serde_json::from_str(&str_command)?;
+        match deserialized.cmd {
+            CommandType::Login { user, password } => {
+                // Identifiers are case insensitive, the parser stores them lowercased
+                let user = user.to_ascii_lowercase();
+                self.database.users.authenticate(&user, &password).await?;
+                Ok((Session::new(user), to_string(&DatabaseResponse::Nothing)?))
+            }
+            _ => Err(DatabaseError::AuthenticationRequired.into()),
+        }
+    }
+
+    async fn require_superuser(&self, session: &Session) -> Result<(), DatabaseError> {
+        if self.database.users.is_superuser(&session.user).await {
+            Ok(())
+        } else {
+            Err(DatabaseError::PermissionDenied)
+        }
+    }
+
+    /// Checks the table privilege needed by the command, user management is checked separately
+    async fn authorize(&self, session: &Session, command: &Command) -> Result<(), DatabaseError> {
+        let (table_name, privilege) = match command {
+            Command::Create { name, .. } => (name, Privilege::Create),
+            Command::Insert { table_name, .. } => (table_name, Privilege::Insert),
+            Command::Update { table_name, .. } => (table_name, Privilege::Update),
+            Command::Delete { table_name, .. } => (table_name, Privilege::Delete),
+            Command::Select { table_name, .. } => (table_name, Privilege::Select),
+            Command::Drop { name } => (name, Privilege::Drop),
+            Command::CreateIndex { table_name, .. } => (table_name, Privilege::Create),
+            Command::AlterTable { table_name, .. } => {
+                return self.database.check_owner(&session.user, table_name).await
+            }
+            Command::CreateUser { .. }
+            | Command::AlterUser { .. }
+            | Command::DropUser { .. }
+            | Command::Grant { .. }
+            | Command::Revoke { .. }
+            | Command::Begin
+            | Command::Commit
+            | Command::Rollback
+            | Command::Savepoint { .. }
+            | Command::RollbackTo { .. }
+            | Command::Release { .. } => return Ok(()),
+        };
+        self.database.check_privilege(&session.user, table_name, privilege).await?;
+        // Returned rows are read and conflicting rows updated as if by SELECT and UPDATE
+        let returns = matches!(
+            command,
+            Command::Insert { returning: Some(_), .. }
+                | Command::Update { returning: Some(_), .. }
+                | Command::Delete { returning: Some(_), .. }
+        );
+        if returns {
+            self.database.check_privilege(&session.user, table_name, Privilege::Select).await?;
+        }
+        if let Command::Insert { on_conflict: OnConflict::Update(_), .. } = command {
+            self.database.check_privilege(&session.user, table_name, Privilege::Update).await?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction left open by a closed connection
+    pub async fn close_session(&self, session: Session) {
+        if let Some(tx) = session.transaction {
+            self.database.rollback(tx).await;
+        }
+    }
+
+    pub async fn process_str(
+        &self,
+        session: &mut Session,
+        str_command: String,
+    ) -> anyhow::Result<String> {
         let deserialized: Action = serde_json::from_str(&str_command).unwrap();
+        // Sessions of dropped users are not allowed to continue
+        if !self.database.users.exists(&session.user).await {
+            return Err(DatabaseError::AuthenticationFailed.into());
+        }
 
         match &deserialized.cmd {
             CommandType::Tables => {
-                return Ok(format!("{:?}", self.database.tables().await?));
+                return Ok(format!("{:?}", self.database.tables(&session.user).await?));
             }
             CommandType::Columns => {
+                self.database
+                    .check_privilege(&session.user, &deserialized.contents, Privilege::Select)
+                    .await?;
                 return Ok(format!(
                     "{:?}",
                     self.database.table_attributes(&deserialized.contents).await?
                 ));
             }
+            CommandType::Login { .. } => {
+                return Err(UserError::Other("Already logged in").into());
+            }
             _ => {}
         };
+        let mut shared = Some(self.catalog.read().await);
         let command =
             crate::parser::get_command(&deserialized.contents, self.database.tables.clone())
                 .await?;
+        // Parsing a schema change does not depend on the current schema
+        let _exclusive = if command.changes_schema() {
+            shared.take();
+            Some(self.catalog.write().await)
+        } else {
+            None
+        };
+        self.authorize(session, &command).await?;
         let response = match command {
-            crate::parser::Command::Create { name, attributes } => {
-                self.database.create_table(&name, attributes).await?
+            Command::Begin => {
+                if session.transaction.is_some() {
+                    return Err(DatabaseError::TransactionInProgress.into());
+                }
+                session.transaction = Some(self.database.begin());
+                DatabaseResponse::Nothing
+            }
+            Command::Commit => {
+                let tx = session.transaction.take().ok_or(DatabaseError::NoTransaction)?;
+                if tx.failed {
+                    // A failed transaction is never committed partially
+                    self.database.rollback(tx).await;
+                    return Err(DatabaseError::TransactionAborted.into());
+                }
+                self.database.commit(tx).await;
+                DatabaseResponse::Nothing
+            }
+            Command::Rollback => {
+                let tx = session.transaction.take().ok_or(DatabaseError::NoTransaction)?;
+                self.database.rollback(tx).await;
+                DatabaseResponse::Nothing
             }
-            crate::parser::Command::Insert { table_name, data } => {
-                self.database.insert(&table_name, data).await?
+            Command::Savepoint { name } => {
+                open_transaction(session)?.savepoint(&name);
+                DatabaseResponse::Nothing
             }
-            crate::parser::Command::Delete { table_name, attr_pos, comparison } => {
-                self.database.delete(&table_name, attr_pos, &comparison).await?
+            Command::RollbackTo { name } => {
+                // Rolling back to a savepoint also recovers a failed transaction
+                let tx = session.transaction.as_mut().ok_or(DatabaseError::NoTransaction)?;
+                self.database.rollback_to_savepoint(tx, &name).await?
             }
-            crate::parser::Command::Select { table_name, attr_pos, comparison, selected } => {
-                self.database.select(&table_name, attr_pos, &comparison, selected).await?
+            Command::Release { name } => {
+                open_transaction(session)?.release_savepoint(&name)?;
+                DatabaseResponse::Nothing
             }
-            crate::parser::Command::Drop { name } => self.database.drop_table(&name).await?,
-            crate::parser::Command::CreateIndex { .. } => DatabaseResponse::Nothing,
+            command => self.execute_in_transaction(session, command).await?,
         };
         let res = to_string(&response)?;
         Ok(res)
     }
+
+    /// Runs the command in the session transaction, or in its own one when there is none
+    async fn execute_in_transaction(
+        &self,
+        session: &mut Session,
+        command: Command,
+    ) -> anyhow::Result<DatabaseResponse> {
+        let Some(mut tx) = session.transaction.take() else {
+            let mut tx = self.database.begin();
+            let result = self.execute(session, &mut tx, command).await;
+            if result.is_ok() {
+                self.database.commit(tx).await;
+            } else {
+                self.database.rollback(tx).await;
+            }
+            return result;
+        };
+        let result = if tx.failed {
+            Err(DatabaseError::TransactionAborted.into())
+        } else if command.changes_schema() {
+            Err(UserError::Other("The schema cannot be changed inside a transaction").into())
+        } else {
+            let result = self.execute(session, &mut tx, command).await;
+            // Statements are not undone on their own, the whole transaction has to be rolled back
+            tx.failed = result.is_err();
+            result
+        };
+        session.transaction = Some(tx);
+        result
+    }
+
+    async fn execute(
+        &self,
+        session: &Session,
+        tx: &mut Transaction,
+        command: Command,
+    ) -> anyhow::Result<DatabaseResponse> {
+        let response = match command {
+            Command::Create { name, attributes } => {
+                self.database.create_table(&name, attributes, &session.user).await?
+            }
+            Command::Insert { table_name, rows, on_conflict, returning } => {
+                self.database.insert_rows(tx, &table_name, rows, &on_conflict, returning).await?
+            }
+            Command::Update { table_name, set, attr_pos, comparison, returning } => {
+                let change = Change::Update(set);
+                self.database
+                    .change(tx, &table_name, attr_pos, &comparison, change, returning)
+                    .await?
+            }
+            Command::Delete { table_name, attr_pos, comparison, returning } => {
+                let change = Change::Delete;
+                self.database
+                    .change(tx, &table_name, attr_pos, &comparison, change, returning)
+                    .await?
+            }
+            Command::Select { table_name, attr_pos, comparison, selected, extracted, order_by } => {
+                let mut response = self
+                    .database
+                    .select(tx, &table_name, attr_pos, &comparison, selected, order_by)
+                    .await?;
+                if let DatabaseResponse::Data(rows) = &mut response {
+                    for (pos, extraction) in &extracted {
+                        for row in rows.iter_mut() {
+                            row.attributes[*pos] = row.attributes[*pos].extract(extraction);
+                        }
+                    }
+                }
+                response
+            }
+            Command::Drop { name } => self.database.drop_table(&name).await?,
+            Command::AlterTable { table_name, alter } => {
+                self.database.alter_table(&table_name, alter).await?
+            }
+            Command::CreateIndex { .. } => DatabaseResponse::Nothing,
+            Command::CreateUser { name, password } => {
+                self.require_superuser(session).await?;
+                self.database.create_user(&name, &password).await?
+            }
+            Command::AlterUser { name, password } => {
+                // Everyone may change their own password
+                if name != session.user {
+                    self.require_superuser(session).await?;
+                }
+                self.database.alter_user(&name, &password).await?
+            }
+            Command::DropUser { name } => {
+                self.require_superuser(session).await?;
+                self.database.drop_user(&name).await?
+            }
+            Command::Grant { privileges, table_name, user } => {
+                self.database.check_grant_option(&session.user, table_name.as_deref()).await?;
+                self.database.grant(&user, table_name.as_deref(), &privileges).await?
+            }
+            Command::Revoke { privileges, table_name, user } => {
+                self.database.check_grant_option(&session.user, table_name.as_deref()).await?;
+                self.database.revoke(&user, table_name.as_deref(), &privileges).await?
+            }
+            Command::Begin
+            | Command::Commit
+            | Command::Rollback
+            | Command::Savepoint { .. }
+            | Command::RollbackTo { .. }
+            | Command::Release { .. } => {
+                unreachable!("Transaction control is handled by process_str")
+            }
+        };
+        Ok(response)
+    }
+}
+
+/// Transaction of the session which still accepts statements
+fn open_transaction(session: &mut Session) -> Result<&mut Transaction, DatabaseError> {
+    match session.transaction.as_mut() {
+        Some(tx) if tx.failed => Err(DatabaseError::TransactionAborted),
+        Some(tx) => Ok(tx),
+        None => Err(DatabaseError::NoTransaction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{DataAttribute, DataAttributes};
+
+    fn query(contents: &str) -> String {
+        let action = Action::create_command_from(contents.to_string(), CommandType::Query);
+        serde_json::to_string(&action).unwrap()
+    }
+
+    fn login(user: &str, password: &str) -> String {
+        let cmd = CommandType::Login { user: user.to_string(), password: password.to_string() };
+        serde_json::to_string(&Action::create_command_from(String::new(), cmd)).unwrap()
+    }
+
+    /// Session of a superuser who created a table with the statement
+    async fn admin_session(processor: &StreamProcessor, create: &str) -> Session {
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut admin, _) = processor.login(login("admin", "admin")).await.unwrap();
+        processor.process_str(&mut admin, query(create)).await.unwrap();
+        admin
+    }
+
+    #[tokio::test]
+    async fn login_required() {
+        let processor = StreamProcessor::default();
+        processor.add_superuser("admin", "admin").await.unwrap();
+        assert!(processor.login(query("select * from t")).await.is_err());
+        assert!(processor.login(login("admin", "wrong")).await.is_err());
+        assert!(processor.login(login("nobody", "admin")).await.is_err());
+        let (session, response) = processor.login(login("Admin", "admin")).await.unwrap();
+        assert_eq!(session.user, "admin");
+        assert!(client_sql::is_login_ok(&response));
+        assert!(!client_sql::is_login_ok("AuthenticationFailed"));
+    }
+
+    #[tokio::test]
+    async fn user_management() {
+        let processor = StreamProcessor::default();
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut admin, _) = processor.login(login("admin", "admin")).await.unwrap();
+        processor.process_str(&mut admin, query("create user alice password 'a'")).await.unwrap();
+        let (mut alice, _) = processor.login(login("alice", "a")).await.unwrap();
+
+        let denied = processor.process_str(&mut alice, query("create user bob password 'b'")).await;
+        assert!(denied.is_err());
+        let denied =
+            processor.process_str(&mut alice, query("alter user admin password 'x'")).await;
+        assert!(denied.is_err());
+        processor.process_str(&mut alice, query("alter user alice password 'b'")).await.unwrap();
+        assert!(processor.login(login("alice", "b")).await.is_ok());
+
+        processor.process_str(&mut admin, query("drop user alice")).await.unwrap();
+        assert!(processor.process_str(&mut alice, query("drop table t")).await.is_err());
+    }
+
+    fn is_permission_denied(result: anyhow::Result<String>) -> bool {
+        matches!(
+            result.unwrap_err().downcast_ref::<DatabaseError>(),
+            Some(DatabaseError::PermissionDenied)
+        )
+    }
+
+    #[tokio::test]
+    async fn table_privileges() {
+        let processor = StreamProcessor::default();
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut admin, _) = processor.login(login("admin", "admin")).await.unwrap();
+        for user in ["alice", "bob"] {
+            let create = format!("create user {} password 'pw'", user);
+            processor.process_str(&mut admin, query(&create)).await.unwrap();
+        }
+        let (mut alice, _) = processor.login(login("alice", "pw")).await.unwrap();
+        let (mut bob, _) = processor.login(login("bob", "pw")).await.unwrap();
+
+        let create = query("create table t (id int primary key, a int)");
+        assert!(is_permission_denied(processor.process_str(&mut alice, create.clone()).await));
+        processor.process_str(&mut admin, query("grant create on * to alice")).await.unwrap();
+        processor.process_str(&mut alice, create).await.unwrap();
+
+        // The owner has every privilege on the table
+        processor.process_str(&mut alice, query("insert into t values (1)")).await.unwrap();
+        processor.process_str(&mut alice, query("select * from t")).await.unwrap();
+        let select = query("select * from t");
+        assert!(is_permission_denied(processor.process_str(&mut bob, select.clone()).await));
+        let grant = query("grant select on t to bob");
+        assert!(is_permission_denied(processor.process_str(&mut bob, grant.clone()).await));
+        processor.process_str(&mut alice, grant).await.unwrap();
+        processor.process_str(&mut bob, select.clone()).await.unwrap();
+        let tables = Action::create_command_from(String::new(), CommandType::Tables);
+        let tables = serde_json::to_string(&tables).unwrap();
+        let listed = processor.process_str(&mut bob, tables.clone()).await.unwrap();
+        assert_eq!(listed, "Names([\"t\"])");
+        let insert = query("insert into t values (2)");
+        assert!(is_permission_denied(processor.process_str(&mut bob, insert).await));
+        let drop = query("drop table t");
+        assert!(is_permission_denied(processor.process_str(&mut bob, drop.clone()).await));
+
+        processor.process_str(&mut admin, query("revoke select on t from bob")).await.unwrap();
+        assert!(is_permission_denied(processor.process_str(&mut bob, select).await));
+        assert_eq!(processor.process_str(&mut bob, tables).await.unwrap(), "Names([])");
+        for statement in [
+            "grant select on t to nobody",
+            "revoke select on t from nobody",
+            "revoke select on * from nobody",
+        ] {
+            let missing = processor.process_str(&mut admin, query(statement)).await.unwrap_err();
+            assert!(
+                matches!(missing.downcast_ref(), Some(DatabaseError::UserDoesNotExist)),
+                "{}",
+                statement
+            );
+        }
+        // Owners are dropped after their tables
+        let drop_alice = query("drop user alice");
+        let owns = processor.process_str(&mut admin, drop_alice.clone()).await.unwrap_err();
+        assert!(matches!(
+            owns.downcast_ref::<DatabaseError>(),
+            Some(DatabaseError::UserOwnsTables)
+        ));
+        processor.process_str(&mut alice, drop).await.unwrap();
+        processor.process_str(&mut admin, drop_alice).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_and_returning() {
+        let processor = StreamProcessor::default();
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut admin, _) = processor.login(login("admin", "admin")).await.unwrap();
+        processor.process_str(&mut admin, query("create user alice password 'a'")).await.unwrap();
+        processor.process_str(&mut admin, query("grant insert on * to alice")).await.unwrap();
+        let (mut alice, _) = processor.login(login("alice", "a")).await.unwrap();
+        let create = query("create table t (id int primary key, a int)");
+        processor.process_str(&mut admin, create).await.unwrap();
+
+        let insert = query("insert into t values (1), (2) returning id");
+        assert!(is_permission_denied(processor.process_str(&mut alice, insert.clone()).await));
+        let upsert = query("insert into t (id, a) values (1, 3) on conflict do update set a = 3");
+        assert!(is_permission_denied(processor.process_str(&mut alice, upsert.clone()).await));
+        processor.process_str(&mut admin, query("grant select on * to alice")).await.unwrap();
+        let inserted = processor.process_str(&mut alice, insert).await.unwrap();
+        let ids = [0, 1].map(|id| DataAttributes { attributes: vec![DataAttribute::Id(id)] });
+        assert_eq!(inserted, to_string(&DatabaseResponse::Data(ids.to_vec())).unwrap());
+        let update = query("update t set a = 5 where id > 0");
+        assert!(is_permission_denied(processor.process_str(&mut alice, update.clone()).await));
+        processor.process_str(&mut admin, query("grant update on * to alice")).await.unwrap();
+        let updated = processor.process_str(&mut alice, update).await.unwrap();
+        assert_eq!(updated, to_string(&DatabaseResponse::Count(1)).unwrap());
+        processor.process_str(&mut alice, upsert).await.unwrap();
+        let delete = query("delete from t returning a");
+        let deleted = processor.process_str(&mut admin, delete).await.unwrap();
+        let a = [1, 3].map(|a| DataAttributes { attributes: vec![DataAttribute::Number(a)] });
+        assert_eq!(deleted, to_string(&DatabaseResponse::Data(a.to_vec())).unwrap());
+    }
+
+    #[tokio::test]
+    async fn affected_rows() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, a int)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t values (1), (2), (3), (4)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        // Rows looked up by id are counted like rows found by scanning every chunk
+        for (delete, count) in [
+            ("delete from t where id = 5", 0),
+            ("delete from t where id = 0", 1),
+            ("delete from t where id = 0", 0),
+            ("delete from t where a >= 3", 2),
+            ("delete from t", 1),
+        ] {
+            let deleted = processor.process_str(&mut admin, query(delete)).await.unwrap();
+            assert_eq!(deleted, to_string(&DatabaseResponse::Count(count)).unwrap(), "{}", delete);
+        }
+    }
+
+    #[tokio::test]
+    async fn blobs() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, b blob)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (b) values (X'00275C22FF'), (X'01')");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        // Every byte is sent as a number, quotes and backslashes need no escaping
+        let select = query("select b from t where b < X'01'");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, "Data([(attributes:[Data([0,39,92,34,255])])])");
+        let select = query("select id from t where b = X'01'");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        let row = DataAttributes { attributes: vec![DataAttribute::Id(1)] };
+        assert_eq!(selected, to_string(&DatabaseResponse::Data(vec![row])).unwrap());
+    }
+
+    #[tokio::test]
+    async fn reals() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, r real, n int)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (r, n) values (0.5, 1), (2, 2), ('NaN', 3), (-0.0, 4)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let ids = |rows: &[i64]| {
+            let rows =
+                rows.iter().map(|id| DataAttributes { attributes: vec![DataAttribute::Id(*id)] });
+            to_string(&DatabaseResponse::Data(rows.collect())).unwrap()
+        };
+        // NaN is above every other number, zero equals negative zero
+        for (select, expected) in [
+            ("select id from t where r >= 1", ids(&[1, 2])),
+            ("select id from t where r = 2", ids(&[1])),
+            ("select id from t where r = 0", ids(&[3])),
+            ("select id from t where n < 1.5", ids(&[0])),
+            ("select id from t where n > 3.0", ids(&[3])),
+        ] {
+            let selected = processor.process_str(&mut admin, query(select)).await.unwrap();
+            assert_eq!(selected, expected, "{}", select);
+        }
+        let select = query("select r from t where n = 1");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, "Data([(attributes:[Real(0.5)])])");
+    }
+
+    #[tokio::test]
+    async fn booleans() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, active boolean)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (active) values (true), (false), (null), (true)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let select = query("select id from t where active");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, "Data([(attributes:[Id(0)]),(attributes:[Id(3)])])");
+        let update = query("update t set active = false where active returning active");
+        let updated = processor.process_str(&mut admin, update).await.unwrap();
+        assert_eq!(updated, "Data([(attributes:[Bool(false)]),(attributes:[Bool(false)])])");
+        let select = query("select id from t where active = false");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(
+            selected,
+            "Data([(attributes:[Id(0)]),(attributes:[Id(1)]),(attributes:[Id(3)])])"
+        );
+    }
+
+    #[tokio::test]
+    async fn dates() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, d date, ts timestamp)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query(
+            "insert into t (d, ts) values ('2024-02-29', '2024-02-29 12:30:00'), \
+             ('1999-12-31', NOW()), ('2100-01-01', '1970-01-01T00:00:10Z')",
+        );
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let select = query("select d, ts from t where ts < '2024-03-01'");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        let expected =
+            "Data([(attributes:[Date(\"2024-02-29\"),Timestamp(\"2024-02-29T12:30:00\")]),\
+                        (attributes:[Date(\"2100-01-01\"),Timestamp(\"1970-01-01T00:00:10\")])])";
+        assert_eq!(selected, expected);
+        // Dates compare with timestamps as their midnight
+        let select = query("select id from t where d <= now()");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, "Data([(attributes:[Id(0)]),(attributes:[Id(1)])])");
+        let select =
+            query("select extract(month from d), extract(epoch from ts) from t where id != 1");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        let expected = "Data([(attributes:[Number(2),Number(1709209800)]),\
+                        (attributes:[Number(1),Number(10)])])";
+        assert_eq!(selected, expected);
+    }
+
+    #[tokio::test]
+    async fn decimals() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, price decimal(10, 2))";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (price) values (12.5), (0.1), (-3)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let select = query("select price from t");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        let expected = "Data([(attributes:[Decimal(\"12.50\")]),\
+                        (attributes:[Decimal(\"0.10\")]),(attributes:[Decimal(\"-3.00\")])])";
+        assert_eq!(selected, expected);
+        for (select, expected) in [
+            ("select id from t where price = 12.500", "Data([(attributes:[Id(0)])])"),
+            ("select id from t where price < 0", "Data([(attributes:[Id(2)])])"),
+            (
+                "select id from t where price <= 0.1",
+                "Data([(attributes:[Id(1)]),(attributes:[Id(2)])])",
+            ),
+        ] {
+            let selected = processor.process_str(&mut admin, query(select)).await.unwrap();
+            assert_eq!(selected, expected, "{}", select);
+        }
+    }
+
+    #[tokio::test]
+    async fn json() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, doc json)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query(
+            r#"insert into t (doc) values ('{"user": {"id": 7}, "active": true}'), ('{"active": false}')"#,
+        );
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let select =
+            query("select doc->'user'->>'id', doc->'user' from t where doc->>'active' = true");
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, r#"Data([(attributes:[Number(7),Json("{\"id\":7}")])])"#);
+        let select = query(r#"select id from t where doc->'user' = '{"id": 7}'"#);
+        let selected = processor.process_str(&mut admin, select).await.unwrap();
+        assert_eq!(selected, "Data([(attributes:[Id(0)])])");
+        // Unwrapped values of another type never match
+        let select = query("select id from t where doc->'user'->>'id' > 'a'");
+        assert_eq!(processor.process_str(&mut admin, select).await.unwrap(), "Data([])");
+    }
+
+    #[tokio::test]
+    async fn nulls() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, a int)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (a) values (1), (null), (3)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let none = "Data([])";
+        for (select, expected) in [
+            ("select id from t where a = null", none),
+            ("select id from t where a != null", none),
+            ("select id from t where id = null", none),
+            ("select id from t where a < 3", "Data([(attributes:[Id(0)])])"),
+            ("select id from t where a != 1", "Data([(attributes:[Id(2)])])"),
+            ("select id from t where a is null", "Data([(attributes:[Id(1)])])"),
+            (
+                "select id from t where a is not null",
+                "Data([(attributes:[Id(0)]),(attributes:[Id(2)])])",
+            ),
+        ] {
+            let selected = processor.process_str(&mut admin, query(select)).await.unwrap();
+            assert_eq!(selected, expected, "{}", select);
+        }
+        let delete = query("delete from t where a is null");
+        assert_eq!(processor.process_str(&mut admin, delete).await.unwrap(), "Count(1)");
+    }
+
+    #[tokio::test]
+    async fn collations() {
+        let processor = StreamProcessor::default();
+        let create =
+            "create table t (id int primary key, a text collate nocase, b text collate unicode)";
+        let mut admin = admin_session(&processor, create).await;
+        let insert = query("insert into t (a, b) values ('Alice', 'ÉCOLE'), ('bob', 'école')");
+        processor.process_str(&mut admin, insert).await.unwrap();
+
+        let both = "Data([(attributes:[Id(0)]),(attributes:[Id(1)])])";
+        for (select, expected) in [
+            ("select id from t where a = 'ALICE'", "Data([(attributes:[Id(0)])])"),
+            ("select id from t where a > 'B'", "Data([(attributes:[Id(1)])])"),
+            ("select id from t where a = 'alice' collate binary", "Data([])"),
+            ("select id from t where b = 'École'", both),
+            ("select id from t where b = 'écOLE' collate nocase", "Data([(attributes:[Id(1)])])"),
+        ] {
+            let selected = processor.process_str(&mut admin, query(select)).await.unwrap();
+            assert_eq!(selected, expected, "{}", select);
+        }
+
+        // Sorted by bytes Carl would come before bob
+        let insert = query("insert into t (a, b) values ('Carl', null)");
+        processor.process_str(&mut admin, insert).await.unwrap();
+        let ids = |ids: [i64; 3]| {
+            let rows = ids.map(|id| DataAttributes { attributes: vec![DataAttribute::Id(id)] });
+            to_string(&DatabaseResponse::Data(rows.to_vec())).unwrap()
+        };
+        for (select, expected) in [
+            ("select id from t order by a", ids([0, 1, 2])),
+            ("select id from t order by a desc", ids([2, 1, 0])),
+            (
+                "select id from t where id > 0 order by b",
+                "Data([(attributes:[Id(1)]),(attributes:[Id(2)])])".to_string(),
+            ),
+            ("select id from t order by b desc", ids([2, 0, 1])),
+        ] {
+            let selected = processor.process_str(&mut admin, query(select)).await.unwrap();
+            assert_eq!(selected, expected, "{}", select);
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, a int)";
+        let mut first = admin_session(&processor, create).await;
+        let (mut second, _) = processor.login(login("admin", "admin")).await.unwrap();
+        let select = query("select a from t");
+        let empty = to_string(&DatabaseResponse::Data(vec![])).unwrap();
+
+        processor.process_str(&mut first, query("begin")).await.unwrap();
+        assert!(processor.process_str(&mut first, query("begin")).await.is_err());
+        processor.process_str(&mut first, query("insert into t values (1)")).await.unwrap();
+        let own = processor.process_str(&mut first, select.clone()).await.unwrap();
+        assert_ne!(own, empty);
+        assert_eq!(processor.process_str(&mut second, select.clone()).await.unwrap(), empty);
+        processor.process_str(&mut first, query("rollback")).await.unwrap();
+        assert_eq!(processor.process_str(&mut first, select.clone()).await.unwrap(), empty);
+        assert!(processor.process_str(&mut first, query("commit")).await.is_err());
+
+        processor.process_str(&mut first, query("start transaction")).await.unwrap();
+        processor.process_str(&mut first, query("insert into t values (1)")).await.unwrap();
+        let drop = query("drop table t");
+        assert!(processor.process_str(&mut first, drop).await.is_err());
+        processor.process_str(&mut first, query("commit")).await.unwrap();
+        assert_eq!(processor.process_str(&mut second, select).await.unwrap(), own);
+    }
+
+    #[tokio::test]
+    async fn alter_table() {
+        let processor = StreamProcessor::default();
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let (mut admin, _) = processor.login(login("admin", "admin")).await.unwrap();
+        processor.process_str(&mut admin, query("create user alice password 'a'")).await.unwrap();
+        processor.process_str(&mut admin, query("grant all on * to alice")).await.unwrap();
+        let (mut alice, _) = processor.login(login("alice", "a")).await.unwrap();
+        let create = query("create table t (id int primary key, a int)");
+        processor.process_str(&mut admin, create).await.unwrap();
+        processor.process_str(&mut admin, query("insert into t values (1)")).await.unwrap();
+
+        let add = query("alter table t add column b text default 'x'");
+        assert!(is_permission_denied(processor.process_str(&mut alice, add.clone()).await));
+        processor.process_str(&mut admin, query("begin")).await.unwrap();
+        assert!(processor.process_str(&mut admin, add.clone()).await.is_err());
+        processor.process_str(&mut admin, query("rollback")).await.unwrap();
+        processor.process_str(&mut admin, add).await.unwrap();
+        let rename = query("alter table t rename column b to c");
+        processor.process_str(&mut admin, rename).await.unwrap();
+        processor.process_str(&mut admin, query("alter table t rename to u")).await.unwrap();
+        let selected = processor.process_str(&mut admin, query("select a, c from u")).await;
+        let row = vec![DataAttribute::Number(1), DataAttribute::String("x".to_string())];
+        let expected = DatabaseResponse::Data(vec![DataAttributes { attributes: row }]);
+        assert_eq!(selected.unwrap(), to_string(&expected).unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_transaction() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, a int)";
+        let mut first = admin_session(&processor, create).await;
+        let (mut second, _) = processor.login(login("admin", "admin")).await.unwrap();
+        processor.process_str(&mut first, query("insert into t values (1)")).await.unwrap();
+
+        let delete = query("delete from t where a = 1");
+        processor.process_str(&mut first, query("begin")).await.unwrap();
+        processor.process_str(&mut second, query("begin")).await.unwrap();
+        processor.process_str(&mut first, delete.clone()).await.unwrap();
+        assert!(processor.process_str(&mut second, delete).await.is_err());
+        // Nothing but a rollback is accepted after an error
+        let insert = query("insert into t values (2)");
+        assert!(processor.process_str(&mut second, insert).await.is_err());
+        let res = processor.process_str(&mut second, query("commit")).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<DatabaseError>(),
+            Some(DatabaseError::TransactionAborted)
+        ));
+        // Closing the connection rolls back the open transaction
+        processor.close_session(first).await;
+        let select = query("select a from t");
+        let empty = to_string(&DatabaseResponse::Data(vec![])).unwrap();
+        assert_ne!(processor.process_str(&mut second, select).await.unwrap(), empty);
+    }
+
+    #[tokio::test]
+    async fn savepoints() {
+        let processor = StreamProcessor::default();
+        let create = "create table t (id int primary key, a int)";
+        let mut admin = admin_session(&processor, create).await;
+        let (mut other, _) = processor.login(login("admin", "admin")).await.unwrap();
+        assert!(processor.process_str(&mut admin, query("savepoint a")).await.is_err());
+        processor.process_str(&mut other, query("insert into t values (5)")).await.unwrap();
+
+        for statement in [
+            "begin",
+            "insert into t values (1)",
+            "savepoint first",
+            "insert into t values (2)",
+            "delete from t where a = 1",
+            "savepoint second",
+            "insert into t values (3)",
+            "rollback to savepoint first",
+            "insert into t values (4)",
+            "release savepoint first",
+        ] {
+            processor.process_str(&mut admin, query(statement)).await.unwrap();
+        }
+        assert!(processor.process_str(&mut admin, query("rollback to second")).await.is_err());
+
+        // A failed statement can be undone by rolling back to a savepoint
+        processor.process_str(&mut other, query("begin")).await.unwrap();
+        processor.process_str(&mut other, query("delete from t where a = 5")).await.unwrap();
+        processor.process_str(&mut admin, query("savepoint before_delete")).await.unwrap();
+        let delete = query("delete from t where a > 0");
+        assert!(processor.process_str(&mut admin, delete).await.is_err());
+        let insert = query("insert into t values (6)");
+        assert!(processor.process_str(&mut admin, insert.clone()).await.is_err());
+        let rollback = query("rollback to before_delete");
+        processor.process_str(&mut admin, rollback).await.unwrap();
+        processor.process_str(&mut admin, insert).await.unwrap();
+        processor.process_str(&mut admin, query("commit")).await.unwrap();
+        processor.process_str(&mut other, query("rollback")).await.unwrap();
+
+        let select = processor.process_str(&mut admin, query("select a from t")).await.unwrap();
+        let values =
+            [5, 1, 4, 6].map(|a| DataAttributes { attributes: vec![DataAttribute::Number(a)] });
+        assert_eq!(select, to_string(&DatabaseResponse::Data(values.to_vec())).unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_ddl_and_dml() {
+        let processor = std::sync::Arc::new(StreamProcessor::default());
+        processor.add_superuser("admin", "admin").await.unwrap();
+        let schemas = [
+            "create table t (id int primary key, a text, b int)",
+            "drop table t",
+            "create table t (id int primary key, b int)",
+            "drop table t",
+        ];
+        let statements = [
+            "insert into t (b) values (1)",
+            "select b from t where b = 1",
+            "select * from t",
+            "select a from t",
+            "delete from t where b = 1",
+        ];
+        let mut tasks = vec![];
+        for worker in 0..8 {
+            let processor = processor.clone();
+            tasks.push(tokio::spawn(async move {
+                let (mut session, _) = processor.login(login("admin", "admin")).await.unwrap();
+                for i in 0..200 {
+                    let statement = match worker {
+                        0 | 1 => schemas[i % schemas.len()],
+                        _ => statements[(i + worker) % statements.len()],
+                    };
+                    let response = match processor.process_str(&mut session, query(statement)).await
+                    {
+                        Ok(response) => ron::from_str::<DatabaseResponse>(&response).unwrap(),
+                        Err(err) => {
+                            // Only the first schema has the column a, rows deleted by a
+                            // concurrent statement cannot be deleted again
+                            let expected = match (
+                                err.downcast_ref::<UserError>(),
+                                err.downcast_ref::<DatabaseError>(),
+                            ) {
+                                (Some(UserError::Other("No such table")), _)
+                                | (_, Some(DatabaseError::TableDoesNotExist)) => true,
+                                (Some(UserError::Other("Column not found")), _) => {
+                                    statement == "select a from t"
+                                }
+                                (_, Some(DatabaseError::DataConflict)) => {
+                                    statement == "delete from t where b = 1"
+                                }
+                                (_, Some(DatabaseError::TableExists)) => {
+                                    statement.starts_with("create")
+                                }
+                                _ => false,
+                            };
+                            assert!(expected, "{}: {}", statement, err);
+                            continue;
+                        }
+                    };
+                    // A changed table must never be read with a stale schema, every row has
+                    // the columns of one of the schemas and b is the last one
+                    let rows = match response {
+                        DatabaseResponse::Data(rows) => rows,
+                        DatabaseResponse::Id(_) | DatabaseResponse::Count(_) => continue,
+                        DatabaseResponse::Nothing if worker < 2 => continue,
+                        response => panic!("{}: {:?}", statement, response),
+                    };
+                    let mut widths =
+                        rows.iter().map(|row| row.attributes.len()).collect::<Vec<_>>();
+                    widths.dedup();
+                    assert!(widths.len() <= 1, "{}: {:?}", statement, rows);
+                    for row in rows {
+                        let expected = match statement {
+                            "select b from t where b = 1" => vec![DataAttribute::Number(1)],
+                            "select a from t" => vec![DataAttribute::None],
+                            _ if row.attributes.len() == 2 => {
+                                vec![row.attributes[0].clone(), DataAttribute::Number(1)]
+                            }
+                            _ => vec![
+                                row.attributes[0].clone(),
+                                DataAttribute::None,
+                                DataAttribute::Number(1),
+                            ],
+                        };
+                        assert_eq!(row.attributes, expected, "{}", statement);
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Both schema sessions end with a drop, a new table starts without the old rows
+        let (mut session, _) = processor.login(login("admin", "admin")).await.unwrap();
+        let select = processor.process_str(&mut session, query("select * from t")).await;
+        assert!(matches!(
+            select.unwrap_err().downcast_ref::<UserError>(),
+            Some(UserError::Other("No such table"))
+        ));
+        for statement in
+            ["create table t (id int primary key, b int)", "insert into t (b) values (2)"]
+        {
+            processor.process_str(&mut session, query(statement)).await.unwrap();
+        }
+        let select = processor.process_str(&mut session, query("select * from t")).await.unwrap();
+        let row =
+            DataAttributes { attributes: vec![DataAttribute::Id(0), DataAttribute::Number(2)] };
+        assert_eq!(select, to_string(&DatabaseResponse::Data(vec![row])).unwrap());
+    }
 }
@@ -0,0 +1,140 @@
+use std::{io, sync::Arc};
+
+use tokio_rustls::rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use tokio_rustls::TlsAcceptor;
+
+fn invalid_data(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Loads a PEM certificate chain and the PEM private key belonging to it
+pub fn load_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(invalid_data)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_data)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(invalid_data)?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// TLS is enabled by setting both the certificate and the key file, setting only one of them
+/// is an error rather than silently accepting plaintext connections
+pub fn configured_acceptor(
+    cert_path: Option<String>,
+    key_path: Option<String>,
+) -> io::Result<Option<TlsAcceptor>> {
+    match (cert_path, key_path) {
+        (Some(cert), Some(key)) => Ok(Some(load_acceptor(&cert, &key)?)),
+        (None, None) => Ok(None),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TLS_CERT and TLS_KEY have to be set together",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client_sql::connection::{connect, ServerAddress, DEFAULT_CONNECT_TIMEOUT};
+    use client_sql::tls::{connect_tls, TlsOptions};
+    use client_sql::{read_message, read_response, write_message, ClientStream};
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    struct TestCert {
+        dir: PathBuf,
+    }
+
+    impl TestCert {
+        fn generate(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("minisql_tls_{}_{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            std::fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+            std::fs::write(dir.join("key.pem"), cert.signing_key.serialize_pem()).unwrap();
+            TestCert { dir }
+        }
+
+        fn cert(&self) -> PathBuf {
+            self.dir.join("cert.pem")
+        }
+
+        fn key(&self) -> PathBuf {
+            self.dir.join("key.pem")
+        }
+    }
+
+    impl Drop for TestCert {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    /// Starts a TLS server answering a single message with the same message
+    async fn echo_server(cert: &TestCert) -> u16 {
+        let acceptor =
+            load_acceptor(cert.cert().to_str().unwrap(), cert.key().to_str().unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let message = read_message(&mut stream).await.unwrap().unwrap();
+                    write_message(&mut stream, &message).await.unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    async fn round_trip(port: u16, options: &TlsOptions) -> std::io::Result<String> {
+        let address = ServerAddress { host: "localhost".to_string(), port };
+        let stream = connect(&address, DEFAULT_CONNECT_TIMEOUT).await?;
+        let mut stream: Box<dyn ClientStream> =
+            Box::new(connect_tls(stream, &address.host, options).await?);
+        write_message(&mut stream, "hello").await?;
+        read_response(&mut stream).await
+    }
+
+    #[tokio::test]
+    async fn trusted_self_signed() {
+        let cert = TestCert::generate("trusted");
+        let port = echo_server(&cert).await;
+        let options = TlsOptions { ca_file: Some(cert.cert()), accept_invalid_certs: false };
+        assert_eq!(round_trip(port, &options).await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn untrusted_self_signed() {
+        let cert = TestCert::generate("untrusted");
+        let port = echo_server(&cert).await;
+        assert!(round_trip(port, &TlsOptions::default()).await.is_err());
+        let options = TlsOptions { ca_file: None, accept_invalid_certs: true };
+        assert_eq!(round_trip(port, &options).await.unwrap(), "hello");
+    }
+
+    #[test]
+    fn missing_files() {
+        assert!(load_acceptor("/nonexistent/cert.pem", "/nonexistent/key.pem").is_err());
+    }
+
+    #[test]
+    fn partial_configuration() {
+        let cert = TestCert::generate("partial");
+        let path = |path: PathBuf| Some(path.to_str().unwrap().to_string());
+        assert!(configured_acceptor(path(cert.cert()), None).is_err());
+        assert!(configured_acceptor(None, path(cert.key())).is_err());
+        assert!(configured_acceptor(None, None).unwrap().is_none());
+        assert!(configured_acceptor(path(cert.cert()), path(cert.key())).unwrap().is_some());
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::{DataAttributes, TableDataChunk};
+use crate::error::DatabaseError;
+
+pub type TxId = u64;
+
+/// Rows which are visible to every transaction, e.g. loaded from a snapshot
+pub const FROZEN_TX: TxId = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxState {
+    Active,
+    Committed,
+    Aborted,
+}
+
+/// A row together with the transactions which created and deleted it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RowVersion {
+    pub xmin: TxId,
+    pub xmax: Option<TxId>,
+    pub data: DataAttributes,
+    // Version replaced by this one, kept until no snapshot can see it anymore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Box<RowVersion>>,
+}
+
+impl RowVersion {
+    pub fn new(xmin: TxId, data: DataAttributes) -> Self {
+        RowVersion { xmin, xmax: None, data, previous: None }
+    }
+
+    /// Adds the version on top of an older one
+    pub fn replacing(mut self, previous: RowVersion) -> Self {
+        self.previous = Some(Box::new(previous));
+        self
+    }
+
+    /// Newest version matching the predicate
+    pub fn find_mut(&mut self, predicate: impl Fn(&RowVersion) -> bool) -> Option<&mut RowVersion> {
+        let mut version = self;
+        loop {
+            if predicate(version) {
+                return Some(version);
+            }
+            version = version.previous.as_deref_mut()?;
+        }
+    }
+
+    /// Drops the versions matching the predicate, None when no version is left
+    pub fn without(self, predicate: &dyn Fn(&RowVersion) -> bool) -> Option<RowVersion> {
+        let previous = self.previous.and_then(|previous| previous.without(predicate));
+        let version = RowVersion { previous: None, ..self };
+        if predicate(&version) {
+            previous
+        } else {
+            Some(RowVersion { previous: previous.map(Box::new), ..version })
+        }
+    }
+
+    /// Drops only the newest version matching the predicate
+    pub fn without_newest(mut self, predicate: &dyn Fn(&RowVersion) -> bool) -> Option<RowVersion> {
+        if predicate(&self) {
+            return self.previous.map(|previous| *previous);
+        }
+        self.previous = self
+            .previous
+            .take()
+            .and_then(|previous| previous.without_newest(predicate))
+            .map(Box::new);
+        Some(self)
+    }
+}
+
+/// How a row is kept in a chunk. After the garbage collection almost every row is visible
+/// to everyone, such rows are stored without any version information
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StoredRow {
+    Frozen(DataAttributes),
+    Versioned(Box<RowVersion>),
+}
+
+impl From<RowVersion> for StoredRow {
+    fn from(row: RowVersion) -> Self {
+        if row.xmin == FROZEN_TX && row.xmax.is_none() && row.previous.is_none() {
+            StoredRow::Frozen(row.data)
+        } else {
+            StoredRow::Versioned(Box::new(row))
+        }
+    }
+}
+
+impl From<StoredRow> for RowVersion {
+    fn from(row: StoredRow) -> Self {
+        match row {
+            StoredRow::Frozen(data) => RowVersion::new(FROZEN_TX, data),
+            StoredRow::Versioned(row) => *row,
+        }
+    }
+}
+
+/// Transactions whose changes a transaction sees, fixed when it begins
+#[derive(Debug)]
+pub struct Snapshot {
+    // Every transaction below it has finished before the snapshot was taken
+    pub xmin: TxId,
+    // Transactions from this one on have started after the snapshot was taken
+    pub xmax: TxId,
+    pub active: HashSet<TxId>,
+}
+
+impl Snapshot {
+    fn includes(&self, id: TxId) -> bool {
+        id < self.xmax && !self.active.contains(&id)
+    }
+}
+
+struct Registry {
+    next_id: TxId,
+    states: HashMap<TxId, TxState>,
+    // Snapshot xmin of every running transaction
+    running: HashMap<TxId, TxId>,
+    // Committed writes whose rows are cleaned up once every snapshot includes them
+    committed: Vec<(TxId, Vec<Write>)>,
+}
+
+/// Keeps the state of transactions which still have unfinished rows in the tables
+pub struct TransactionManager {
+    registry: RwLock<Registry>,
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        let registry = Registry {
+            next_id: FROZEN_TX + 1,
+            states: HashMap::new(),
+            running: HashMap::new(),
+            committed: vec![],
+        };
+        TransactionManager { registry: RwLock::new(registry) }
+    }
+}
+
+impl TransactionManager {
+    pub fn begin(&self) -> (TxId, Snapshot) {
+        let mut registry = self.registry.write().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        // Committed transactions which have not been released yet are not active anymore
+        let active: HashSet<TxId> = registry
+            .running
+            .keys()
+            .filter(|running| registry.states.get(running) == Some(&TxState::Active))
+            .copied()
+            .collect();
+        let snapshot =
+            Snapshot { xmin: active.iter().copied().min().unwrap_or(id), xmax: id, active };
+        registry.states.insert(id, TxState::Active);
+        registry.running.insert(id, snapshot.xmin);
+        (id, snapshot)
+    }
+
+    pub fn state(&self, id: TxId) -> TxState {
+        if id == FROZEN_TX {
+            return TxState::Committed;
+        }
+        // Transactions are forgotten only after none of their rows refer to them
+        *self.registry.read().unwrap().states.get(&id).unwrap_or(&TxState::Aborted)
+    }
+
+    pub fn abort(&self, id: TxId) {
+        self.registry.write().unwrap().states.insert(id, TxState::Aborted);
+    }
+
+    pub fn commit(&self, id: TxId, writes: Vec<Write>) {
+        let mut registry = self.registry.write().unwrap();
+        registry.states.insert(id, TxState::Committed);
+        registry.committed.push((id, writes));
+    }
+
+    /// Ends a transaction, committed ones are remembered until their rows are cleaned up
+    fn release(&self, id: TxId) {
+        let mut registry = self.registry.write().unwrap();
+        registry.running.remove(&id);
+        if registry.states.get(&id) != Some(&TxState::Committed) {
+            registry.states.remove(&id);
+        }
+    }
+
+    /// Takes the committed writes which every running and future transaction sees
+    pub fn take_collectable(&self) -> Vec<(TxId, Vec<Write>)> {
+        let mut registry = self.registry.write().unwrap();
+        let horizon = registry.running.values().copied().min().unwrap_or(registry.next_id);
+        let (collectable, pending) =
+            registry.committed.drain(..).partition(|(id, _)| *id < horizon);
+        registry.committed = pending;
+        collectable
+    }
+
+    pub fn forget(&self, id: TxId) {
+        self.registry.write().unwrap().states.remove(&id);
+    }
+}
+
+/// What a single transaction sees of the tables
+#[derive(Clone)]
+pub struct TxView {
+    manager: Arc<TransactionManager>,
+    snapshot: Arc<Snapshot>,
+    pub id: TxId,
+}
+
+impl TxView {
+    fn is_done(&self, id: TxId) -> bool {
+        id == self.id || self.snapshot.includes(id) && self.manager.state(id) == TxState::Committed
+    }
+
+    /// Rows committed before the snapshot and the rows written by the transaction itself are visible
+    pub fn sees(&self, row: &RowVersion) -> bool {
+        self.is_done(row.xmin) && !row.xmax.is_some_and(|xmax| self.is_done(xmax))
+    }
+
+    /// Version of the row the transaction sees, at most one version of a row is visible
+    pub fn visible<'a>(&self, row: &'a RowVersion) -> Option<&'a RowVersion> {
+        let mut version = row;
+        loop {
+            if self.sees(version) {
+                return Some(version);
+            }
+            version = version.previous.as_deref()?;
+        }
+    }
+
+    /// Data of the version the transaction sees
+    pub fn visible_data<'a>(&self, row: &'a StoredRow) -> Option<&'a DataAttributes> {
+        match row {
+            StoredRow::Frozen(data) => Some(data),
+            StoredRow::Versioned(row) => self.visible(row).map(|version| &version.data),
+        }
+    }
+
+    /// Versions inserted by an aborted transaction are never visible again
+    pub fn is_dead(&self, row: &RowVersion) -> bool {
+        row.xmin != self.id && self.manager.state(row.xmin) == TxState::Aborted
+    }
+
+    /// Whether the newest version of a row is deleted as far as the transaction is concerned,
+    /// a new version with the same id may be added then
+    pub fn is_deleted(&self, row: &RowVersion) -> bool {
+        row.xmax.is_some_and(|xmax| self.is_done(xmax))
+    }
+
+    /// Rows deleted by another running or later committed transaction cannot be deleted again
+    pub fn can_delete(&self, row: &RowVersion) -> bool {
+        match row.xmax {
+            Some(xmax) if xmax != self.id => self.manager.state(xmax) == TxState::Aborted,
+            _ => true,
+        }
+    }
+
+    /// A new version may only be put on top of the row when its newest version is the
+    /// visible one, otherwise the row was changed by a transaction the snapshot does not see
+    pub fn can_replace(&self, row: &RowVersion) -> bool {
+        self.sees(row) && self.can_delete(row)
+    }
+}
+
+/// Row changed by a transaction, needed to finish the row on commit or rollback,
+/// the chunk is kept instead of the table name so renaming the table does not lose it
+#[derive(Debug, Clone)]
+pub enum Write {
+    Insert { chunk: Arc<TableDataChunk>, id: i64 },
+    Delete { chunk: Arc<TableDataChunk>, id: i64 },
+}
+
+impl PartialEq for Write {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Write::Insert { chunk, id }, Write::Insert { chunk: other, id: other_id })
+            | (Write::Delete { chunk, id }, Write::Delete { chunk: other, id: other_id }) => {
+                Arc::ptr_eq(chunk, other) && id == other_id
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct Savepoint {
+    name: String,
+    // Number of writes made before the savepoint
+    writes: usize,
+}
+
+pub struct Transaction {
+    pub view: TxView,
+    // Set after a failed statement, only a rollback is accepted afterwards
+    pub failed: bool,
+    pub writes: Vec<Write>,
+    savepoints: Vec<Savepoint>,
+}
+
+impl Transaction {
+    pub fn new(manager: Arc<TransactionManager>) -> Self {
+        let (id, snapshot) = manager.begin();
+        let view = TxView { manager, snapshot: Arc::new(snapshot), id };
+        Transaction { view, failed: false, writes: vec![], savepoints: vec![] }
+    }
+
+    pub fn id(&self) -> TxId {
+        self.view.id
+    }
+
+    pub fn manager(&self) -> &TransactionManager {
+        &self.view.manager
+    }
+
+    /// A savepoint with the same name as an earlier one hides it until released
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.push(Savepoint { name: name.to_string(), writes: self.writes.len() });
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, DatabaseError> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or(DatabaseError::SavepointDoesNotExist)
+    }
+
+    /// Forgets the savepoint and every savepoint defined after it, the writes are kept
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let position = self.find_savepoint(name)?;
+        self.savepoints.truncate(position);
+        Ok(())
+    }
+
+    /// Returns the writes made after the savepoint, the caller has to undo them
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<Vec<Write>, DatabaseError> {
+        let position = self.find_savepoint(name)?;
+        self.savepoints.truncate(position + 1);
+        self.failed = false;
+        Ok(self.writes.split_off(self.savepoints[position].writes))
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        // Rows left behind by an unfinished transaction belong to an unknown, thus aborted, one
+        self.manager().release(self.id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visibility() {
+        let manager = Arc::new(TransactionManager::default());
+        let first = Transaction::new(manager.clone());
+        let second = Transaction::new(manager.clone());
+        let row = RowVersion::new(first.id(), DataAttributes::default());
+        assert!(first.view.sees(&row));
+        assert!(!second.view.sees(&row));
+        manager.commit(first.id(), vec![]);
+        // The snapshot of the second transaction was taken before the commit
+        assert!(!second.view.sees(&row));
+        let third = Transaction::new(manager.clone());
+        assert!(third.view.sees(&row));
+
+        let deleted = RowVersion { xmax: Some(third.id()), ..row };
+        assert!(!third.view.sees(&deleted));
+        let fourth = Transaction::new(manager.clone());
+        assert!(fourth.view.sees(&deleted));
+        assert!(!fourth.view.can_delete(&deleted));
+        manager.abort(third.id());
+        assert!(fourth.view.can_delete(&deleted));
+    }
+
+    #[test]
+    fn collect_after_snapshots_end() {
+        let manager = Arc::new(TransactionManager::default());
+        let reader = Transaction::new(manager.clone());
+        let writer = Transaction::new(manager.clone());
+        let writes = vec![Write::Insert { chunk: Arc::default(), id: 0 }];
+        manager.commit(writer.id(), writes.clone());
+        drop(writer);
+        // The reader has not seen the commit yet
+        assert!(manager.take_collectable().is_empty());
+        drop(reader);
+        assert_eq!(manager.take_collectable(), vec![(2, writes)]);
+    }
+
+    #[test]
+    fn dropped_transaction_is_aborted() {
+        let manager = Arc::new(TransactionManager::default());
+        let tx = Transaction::new(manager.clone());
+        let row = RowVersion::new(tx.id(), DataAttributes::default());
+        drop(tx);
+        assert_eq!(manager.state(row.xmin), TxState::Aborted);
+    }
+
+    #[test]
+    fn frozen_rows() {
+        let manager = Arc::new(TransactionManager::default());
+        let tx = Transaction::new(manager);
+        assert!(tx.view.sees(&RowVersion::new(FROZEN_TX, DataAttributes::default())));
+    }
+
+    #[test]
+    fn stored_rows() {
+        let manager = Arc::new(TransactionManager::default());
+        let tx = Transaction::new(manager);
+        let data = DataAttributes { attributes: vec![crate::database::DataAttribute::Number(1)] };
+        let frozen = StoredRow::from(RowVersion::new(FROZEN_TX, data.clone()));
+        assert_eq!(frozen, StoredRow::Frozen(data.clone()));
+        assert_eq!(RowVersion::from(frozen), RowVersion::new(FROZEN_TX, data.clone()));
+
+        let deleted =
+            RowVersion { xmax: Some(tx.id()), ..RowVersion::new(FROZEN_TX, data.clone()) };
+        for row in [RowVersion::new(tx.id(), data.clone()), deleted] {
+            let stored = StoredRow::from(row.clone());
+            assert!(matches!(stored, StoredRow::Versioned(_)));
+            assert_eq!(tx.view.visible_data(&stored), tx.view.visible(&row).map(|row| &row.data));
+            assert_eq!(RowVersion::from(stored), row);
+        }
+    }
+
+    #[test]
+    fn version_chain() {
+        let manager = Arc::new(TransactionManager::default());
+        let old = Transaction::new(manager.clone());
+        let data =
+            |age| DataAttributes { attributes: vec![crate::database::DataAttribute::Number(age)] };
+        let updater = Transaction::new(manager.clone());
+        let first = RowVersion { xmax: Some(updater.id()), ..RowVersion::new(FROZEN_TX, data(1)) };
+        let row = RowVersion::new(updater.id(), data(2)).replacing(first);
+        assert_eq!(old.view.visible(&row).unwrap().data, data(1));
+        assert_eq!(updater.view.visible(&row).unwrap().data, data(2));
+        assert!(updater.view.is_deleted(row.previous.as_ref().unwrap()));
+
+        let mut row = row.without_newest(&|version| version.xmin == updater.id()).unwrap();
+        assert!(row.previous.is_none());
+        row.find_mut(|version| version.xmax == Some(updater.id())).unwrap().xmax = None;
+        assert_eq!(row, RowVersion::new(FROZEN_TX, data(1)));
+        assert!(row.without(&|version| version.xmin == FROZEN_TX).is_none());
+    }
+
+    #[test]
+    fn savepoints() {
+        let manager = Arc::new(TransactionManager::default());
+        let mut tx = Transaction::new(manager);
+        let chunk = Arc::<TableDataChunk>::default();
+        let write = |id| Write::Insert { chunk: chunk.clone(), id };
+        tx.writes.push(write(0));
+        tx.savepoint("a");
+        tx.writes.push(write(1));
+        tx.savepoint("b");
+        tx.writes.push(write(2));
+        tx.savepoint("a");
+        tx.writes.push(write(3));
+        tx.failed = true;
+
+        assert_eq!(tx.rollback_to_savepoint("a").unwrap(), vec![write(3)]);
+        assert!(!tx.failed);
+        tx.release_savepoint("a").unwrap();
+        assert_eq!(tx.rollback_to_savepoint("a").unwrap(), vec![write(1), write(2)]);
+        tx.release_savepoint("a").unwrap();
+        assert!(tx.rollback_to_savepoint("b").is_err());
+        assert_eq!(tx.writes, vec![write(0)]);
+    }
+}